@@ -0,0 +1,662 @@
+use penguin::{
+    board2::{Board2, Move},
+    eval::TableEvaluator,
+    game::{Game, GameResult, RepetitionRule},
+    perft::expected_perft,
+    protocol::{self, parse_position},
+    solve::Database,
+    tune::{self, SelfPlayOptions},
+    PartialSearchResult, SearchAlgorithm, SearchLimits, SearchOptions, Searcher,
+    DEFAULT_TT_SIZE_MB,
+};
+use std::{
+    cell::Cell,
+    env,
+    io::{self, BufRead, Write},
+    process, thread,
+    time::{Duration, Instant},
+};
+
+const STACK_SIZE: usize = 400 * 1024 * 1024;
+
+const USAGE: &str = "usage: penguin <command> [position] [options]
+
+commands:
+    analyse [position]    search a position and print each completed depth
+    play [position]       play against the engine on stdin/stdout
+    selfplay [position]   let the engine play both sides
+    perft [position]      count move paths to a given depth
+    bench                 compare search algorithms on fixed positions, to --depth (default 9)
+    replay <file>         print a saved game, up to --plies moves
+    weights               print the evaluation weights in effect, as JSON for --weights
+    tune [position]       fit the evaluation weights to --games self-play games from --weights
+    solve                 solve the whole game by retrograde analysis, saving the database to --save
+                          (needs about 1.7 GB of memory and as much disk)
+    probe <file> [position]  print the value of a position in a database saved by `solve`
+    uci                   speak the line-based engine protocol on stdin/stdout
+
+positions:
+    start                 the original starting position (default)
+    inversed              the starting position with kings swapped
+    '<rows> <side>'       position notation, e.g. 'PPKPP/5/5/5/ppkpp w'
+    <p0,p1,...,p10>       11 comma separated values as taken by Board2::try_from_positions
+
+options:
+    --depth <n>           stop searching after depth n (perft: depth to count, default 4)
+    --time <seconds>      stop searching after the given wall-clock time
+    --human <white|black> side played by the human in `play` (default white)
+    --plies <n>           maximum number of plies in `selfplay` and `tune` (default 200)
+    --save <file>         save the game played by `play` or `selfplay`, the weights fitted by `tune`
+                          or the database of `solve`
+    --games <n>           number of self-play games played by `tune` (default 100)
+    --hash <mb>           size of the transposition table in megabytes (default 32)
+    --threads <n>         number of search threads (default 1)
+    --algorithm <name>    pvs (default) or alphabeta
+    --multipv <n>         number of principal variations shown by `analyse` (default 1)
+    --weights <file>      evaluation weights in JSON, see `weights` for the format
+    --repetition <rule>   forbidden (default), draw (on threefold repetition) or loss
+                          (for the player who repeats a position)";
+
+#[derive(Clone)]
+struct Options {
+    position: Board2,
+    depth: Option<usize>,
+    time: Option<Duration>,
+    human_is_white: bool,
+    plies: usize,
+    save: Option<String>,
+    repetition_rule: RepetitionRule,
+    tt_size_mb: usize,
+    threads: usize,
+    algorithm: SearchAlgorithm,
+    multi_pv: usize,
+    weights: TableEvaluator,
+    games: usize,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    // Spawn thread with explicit stack size
+    let child = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || run(args))
+        .unwrap();
+
+    let code = child.join().unwrap();
+    process::exit(code);
+}
+
+fn run(args: Vec<String>) -> i32 {
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    let (file, rest) = match (command, rest.split_first()) {
+        ("replay" | "probe", Some((file, rest))) => (Some(file.as_str()), rest),
+        _ => (None, rest),
+    };
+    let options = match parse_options(rest) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return 2;
+        }
+    };
+    match command {
+        "analyse" | "analyze" => analyse(&options),
+        "play" => play(&options),
+        "selfplay" => selfplay(&options),
+        "perft" => return perft(&options),
+        "bench" => bench(&options),
+        "replay" => match file {
+            Some(file) => return replay(file, &options),
+            None => {
+                eprintln!("error: missing game file\n\n{}", USAGE);
+                return 2;
+            }
+        },
+        "probe" => match file {
+            Some(file) => return probe(file, &options),
+            None => {
+                eprintln!("error: missing database file\n\n{}", USAGE);
+                return 2;
+            }
+        },
+        "solve" => return solve(&options),
+        "uci" => protocol::run(io::stdin().lock(), io::stdout()),
+        "weights" => println!("{}", options.weights.to_json()),
+        "tune" => return tune(&options),
+        "help" | "--help" | "-h" => println!("{}", USAGE),
+        _ => {
+            eprintln!("error: unknown command `{}`\n\n{}", command, USAGE);
+            return 2;
+        }
+    }
+    0
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        position: Board2::new_original(),
+        depth: None,
+        time: None,
+        human_is_white: true,
+        plies: 200,
+        save: None,
+        repetition_rule: RepetitionRule::default(),
+        tt_size_mb: DEFAULT_TT_SIZE_MB,
+        threads: 1,
+        algorithm: SearchAlgorithm::default(),
+        multi_pv: 1,
+        weights: TableEvaluator::default(),
+        games: 100,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", name))
+        };
+        match arg.as_str() {
+            "--depth" => {
+                let v = value("--depth")?;
+                options.depth = Some(v.parse().map_err(|_| format!("bad depth `{}`", v))?);
+            }
+            "--time" => {
+                let v = value("--time")?;
+                let secs: f64 = v.parse().map_err(|_| format!("bad time `{}`", v))?;
+                options.time = Some(Duration::from_secs_f64(secs));
+            }
+            "--human" => {
+                options.human_is_white = match value("--human")?.as_str() {
+                    "white" => true,
+                    "black" => false,
+                    v => return Err(format!("bad side `{}`", v)),
+                };
+            }
+            "--save" => options.save = Some(value("--save")?.clone()),
+            "--repetition" => options.repetition_rule = value("--repetition")?.parse()?,
+            "--hash" => {
+                let v = value("--hash")?;
+                options.tt_size_mb = v.parse().map_err(|_| format!("bad size `{}`", v))?;
+            }
+            "--threads" => {
+                let v = value("--threads")?;
+                options.threads = match v.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("bad thread count `{}`", v)),
+                };
+            }
+            "--algorithm" => options.algorithm = value("--algorithm")?.parse()?,
+            "--multipv" => {
+                let v = value("--multipv")?;
+                options.multi_pv = match v.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("bad line count `{}`", v)),
+                };
+            }
+            "--weights" => {
+                let v = value("--weights")?;
+                options.weights = TableEvaluator::load(v).map_err(|e| format!("{}: {}", v, e))?;
+            }
+            "--games" => {
+                let v = value("--games")?;
+                options.games = v.parse().map_err(|_| format!("bad game count `{}`", v))?;
+            }
+            "--plies" => {
+                let v = value("--plies")?;
+                options.plies = v.parse().map_err(|_| format!("bad ply count `{}`", v))?;
+            }
+            position => options.position = parse_position(position)?,
+        }
+    }
+    Ok(options)
+}
+
+fn format_pv(best_path: &[Move]) -> String {
+    best_path
+        .iter()
+        .rev()
+        .map(|m| m.to_notation())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn format_score(score: i32, mate: Option<i32>) -> String {
+    match mate {
+        Some(plies) => format!("mate {:+}", plies),
+        None => score.to_string(),
+    }
+}
+
+fn format_partial(partial: &PartialSearchResult, elapsed: Duration) -> String {
+    let pv = format_pv(&partial.result.best_path);
+    let score = format_score(partial.result.score, partial.mate);
+    let first_move_cutoff_rate = 100 * partial.first_move_cutoffs / partial.cutoffs.max(1);
+    let mut s = format!(
+        "depth {} score {} nodes {} qnodes {} cutoffs {} ({}% first) tt {} time {}ms pv {}",
+        partial.depth,
+        score,
+        partial.nodes_searched,
+        partial.quiescence_nodes,
+        partial.cutoffs,
+        first_move_cutoff_rate,
+        partial.transposition_table_size,
+        elapsed.as_millis(),
+        pv
+    );
+    if partial.lines.len() > 1 {
+        for (i, line) in partial.lines.iter().enumerate() {
+            s.push_str(&format!(
+                "\n    {}. score {} pv {}",
+                i + 1,
+                format_score(line.score, line.mate),
+                format_pv(&line.best_path)
+            ));
+        }
+    }
+    s
+}
+
+/// Runs an iterative deepening search bounded by the depth and time options,
+/// printing a line for every completed depth.
+fn search(
+    searcher: &mut Searcher,
+    board: Board2,
+    history: &[Board2],
+    options: &Options,
+) -> Option<Move> {
+    let start = Instant::now();
+    let limits = SearchLimits {
+        max_depth: options.depth,
+        hard_time_ms: options.time.map(|t| t.as_millis() as u64),
+        ..SearchLimits::default()
+    };
+    searcher.find_best_move(
+        board,
+        || false,
+        |partial| println!("{}", format_partial(&partial, start.elapsed())),
+        limits,
+        history.to_vec(),
+    )
+}
+
+fn with_default_time(options: &Options, default: Duration) -> Options {
+    let mut options = options.clone();
+    if options.depth.is_none() && options.time.is_none() {
+        options.time = Some(default);
+    }
+    options
+}
+
+fn analyse(options: &Options) {
+    let options = with_default_time(options, Duration::from_secs(10));
+    println!("{}", options.position.to_notation());
+    println!("{:?}", options.position);
+    let search_options = SearchOptions {
+        repetition_rule: options.repetition_rule,
+        tt_size_mb: options.tt_size_mb,
+        threads: options.threads,
+        algorithm: options.algorithm,
+        multi_pv: options.multi_pv,
+        ..SearchOptions::default()
+    };
+    let mut searcher = Searcher::with_evaluator(search_options, options.weights.clone());
+    match search(&mut searcher, options.position, &[], &options) {
+        Some(m) => println!("bestmove {}", m.to_notation()),
+        None => println!("bestmove none"),
+    }
+}
+
+fn finish(game: &Game, options: &Options) {
+    println!("{:?}", game.current());
+    match game.result() {
+        GameResult::WhiteWins => println!("white wins"),
+        GameResult::BlackWins => println!("black wins"),
+        GameResult::Draw => println!("draw"),
+        GameResult::Ongoing => println!("no result after {} plies", game.moves().len()),
+    }
+    if let Some(path) = &options.save {
+        match game.save(path) {
+            Ok(()) => println!("saved game to {}", path),
+            Err(e) => eprintln!("error: cannot save game to {}: {}", path, e),
+        }
+    }
+}
+
+/// A searcher for the engine's moves in `game`, kept for the whole game.
+fn game_searcher(game: &Game, options: &Options) -> Searcher {
+    let search_options = SearchOptions {
+        repetition_rule: game.repetition_rule(),
+        tt_size_mb: options.tt_size_mb,
+        threads: options.threads,
+        algorithm: options.algorithm,
+        ..SearchOptions::default()
+    };
+    Searcher::with_evaluator(search_options, options.weights.clone())
+}
+
+/// Searches the current position of `game`, falling back to any move that
+/// does not repeat a position if the search did not complete a single depth.
+/// `None` if the side to move has no move, which ends the game.
+fn engine_move(searcher: &mut Searcher, game: &Game, options: &Options) -> Option<Move> {
+    search(searcher, game.current(), game.positions(), options)
+        .or_else(|| game.legal_moves().first().map(|&(m, _)| m))
+}
+
+/// Ends `game` as lost for the side to move, which has no move. Only a game
+/// set up in such a position has no result yet.
+fn end_without_moves(game: &mut Game) {
+    println!("no legal move");
+    game.set_result(if game.current().maximizing() {
+        GameResult::BlackWins
+    } else {
+        GameResult::WhiteWins
+    });
+}
+
+fn play(options: &Options) {
+    let options = with_default_time(options, Duration::from_secs(2));
+    let mut game = Game::with_repetition_rule(options.position, options.repetition_rule);
+    let (white, black) = if options.human_is_white {
+        ("human", "penguin")
+    } else {
+        ("penguin", "human")
+    };
+    game.metadata.white = white.to_string();
+    game.metadata.black = black.to_string();
+    let mut searcher = game_searcher(&game, &options);
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        if game.result() != GameResult::Ongoing {
+            break;
+        }
+        let board = game.current();
+        println!("{:?}", board);
+        if board.maximizing() == options.human_is_white {
+            print!("your move (e.g. c1-c4): ");
+            io::stdout().flush().unwrap();
+            let line = match lines.next() {
+                Some(Ok(line)) => line,
+                _ => break,
+            };
+            let line = line.trim();
+            if line == "quit" {
+                break;
+            }
+            if let Err(e) = game.play_notation(line) {
+                println!("{}", e);
+            }
+        } else {
+            let m = match engine_move(&mut searcher, &game, &options) {
+                Some(m) => m,
+                None => {
+                    end_without_moves(&mut game);
+                    break;
+                }
+            };
+            println!("engine plays {}", m.to_notation());
+            game.play(m).unwrap();
+        }
+    }
+    finish(&game, &options);
+}
+
+fn selfplay(options: &Options) {
+    let options = with_default_time(options, Duration::from_secs(1));
+    let mut game = Game::with_repetition_rule(options.position, options.repetition_rule);
+    game.metadata.white = "penguin".to_string();
+    game.metadata.black = "penguin".to_string();
+    let mut searcher = game_searcher(&game, &options);
+    while game.moves().len() < options.plies {
+        if game.result() != GameResult::Ongoing {
+            break;
+        }
+        let board = game.current();
+        println!("{:?}", board);
+        let m = match engine_move(&mut searcher, &game, &options) {
+            Some(m) => m,
+            None => {
+                end_without_moves(&mut game);
+                break;
+            }
+        };
+        println!("ply {}: {}", game.moves().len() + 1, m.to_notation());
+        game.play(m).unwrap();
+    }
+    finish(&game, &options);
+}
+
+fn replay(path: &str, options: &Options) -> i32 {
+    let game = match Game::load(path) {
+        Ok(game) => game,
+        Err(e) => {
+            eprintln!("error: cannot load {}: {}", path, e);
+            return 1;
+        }
+    };
+    let ply = options.plies.min(game.moves().len());
+    println!(
+        "{} vs {} ({:?}, {})",
+        game.metadata.white, game.metadata.black, game.metadata.variant, game.metadata.date
+    );
+    println!("{}", game.start().to_notation());
+    for (i, m) in game.moves().iter().take(ply).enumerate() {
+        println!("ply {}: {}", i + 1, m.to_notation());
+    }
+    let board = game.position_at(ply).unwrap();
+    println!("{}", board.to_notation());
+    println!("{:?}", board);
+    println!("result: {:?}", game.result());
+    0
+}
+
+fn perft(options: &Options) -> i32 {
+    let depth = options.depth.unwrap_or(4);
+    let start = Instant::now();
+    let mut total = 0;
+    for (m, count) in options.position.perft_divide(depth) {
+        println!("{}: {}", m.to_notation(), count);
+        total += count;
+    }
+    if depth == 0 {
+        total = 1;
+    }
+    println!(
+        "perft {}: {} ({}ms)",
+        depth,
+        total,
+        start.elapsed().as_millis()
+    );
+    match expected_perft(options.position, depth) {
+        Some(expected) if expected != total => {
+            println!("MISMATCH: expected {}", expected);
+            1
+        }
+        Some(_) => {
+            println!("matches the expected count");
+            0
+        }
+        None => 0,
+    }
+}
+
+/// Positions searched by `bench`: the two starting positions and a few from
+/// self-play games.
+const BENCH_POSITIONS: [&str; 6] = [
+    "start",
+    "inversed",
+    "1P1PP/1p3/5/P1K2/1pkpp w",
+    "P1kPP/P1Kp1/3p1/5/1p2p w",
+    "P1k1P/1PKpP/1p1p1/5/2p2 w",
+    "1PkP1/1PKpP/3p1/5/2pp1 w",
+];
+
+/// Searches every bench position to a fixed depth with each algorithm,
+/// printing the nodes and time it took.
+fn bench(options: &Options) {
+    let depth = options.depth.unwrap_or(9);
+    for algorithm in [SearchAlgorithm::AlphaBeta, SearchAlgorithm::Pvs] {
+        let mut total_nodes = 0;
+        let mut total_time = Duration::ZERO;
+        for position in BENCH_POSITIONS {
+            let board = parse_position(position).unwrap();
+            let nodes = Cell::new(0);
+            let start = Instant::now();
+            let search_options = SearchOptions {
+                repetition_rule: options.repetition_rule,
+                tt_size_mb: options.tt_size_mb,
+                threads: options.threads,
+                algorithm,
+                ..SearchOptions::default()
+            };
+            let mut searcher = Searcher::with_evaluator(search_options, options.weights.clone());
+            let best_move = searcher.find_best_move(
+                board,
+                || false,
+                |partial| nodes.set(nodes.get() + partial.nodes_searched),
+                SearchLimits {
+                    max_depth: Some(depth),
+                    ..SearchLimits::default()
+                },
+                vec![],
+            );
+            let elapsed = start.elapsed();
+            println!(
+                "{:?} {}: nodes {} time {}ms bestmove {}",
+                algorithm,
+                position,
+                nodes.get(),
+                elapsed.as_millis(),
+                best_move.map_or("none".to_string(), |m| m.to_notation())
+            );
+            total_nodes += nodes.get();
+            total_time += elapsed;
+        }
+        println!(
+            "{:?} total: nodes {} time {}ms",
+            algorithm,
+            total_nodes,
+            total_time.as_millis()
+        );
+    }
+}
+
+/// Plays self-play games at a fixed depth with the current weights and fits
+/// the weights to their results, printing them and saving them to --save.
+fn tune(options: &Options) -> i32 {
+    let self_play_options = SelfPlayOptions {
+        start: options.position,
+        repetition_rule: options.repetition_rule,
+        depth: options.depth.unwrap_or(4),
+        max_plies: options.plies,
+        ..SelfPlayOptions::default()
+    };
+    let mut rng = rand::thread_rng();
+    let start = Instant::now();
+    let games = (0..options.games)
+        .map(|i| {
+            let game = tune::self_play(&self_play_options, &options.weights, &mut rng);
+            println!(
+                "game {}/{}: {:?} in {} plies",
+                i + 1,
+                options.games,
+                game.result(),
+                game.moves().len()
+            );
+            game
+        })
+        .collect::<Vec<_>>();
+    let samples = tune::samples(&games);
+    println!(
+        "{} positions from {} games ({}ms)",
+        samples.len(),
+        games.len(),
+        start.elapsed().as_millis()
+    );
+    if samples.is_empty() {
+        eprintln!("error: no finished game to tune on");
+        return 1;
+    }
+    let mut pass = 0;
+    let weights = tune::tune(&samples, &options.weights, |error| {
+        println!("pass {}: error {:.6}", pass, error);
+        pass += 1;
+    });
+    let json = weights.to_json();
+    println!("{}", json);
+    if let Some(path) = &options.save {
+        match std::fs::write(path, format!("{}\n", json)) {
+            Ok(()) => println!("saved weights to {}", path),
+            Err(e) => {
+                eprintln!("error: cannot save weights to {}: {}", path, e);
+                return 1;
+            }
+        }
+    }
+    0
+}
+
+/// Solves the game and saves the database, printing how many positions are
+/// decided at every distance and the values of the starting positions.
+fn solve(options: &Options) -> i32 {
+    let path = match &options.save {
+        Some(path) => path,
+        None => {
+            eprintln!("error: missing --save for the database\n\n{}", USAGE);
+            return 2;
+        }
+    };
+    let start = Instant::now();
+    let database = Database::solve(path, |plies, positions| {
+        println!(
+            "{} positions decided in {} plies ({}s)",
+            positions,
+            plies,
+            start.elapsed().as_secs()
+        )
+    });
+    let mut database = match database {
+        Ok(database) => database,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        }
+    };
+    println!("saved database to {}", path);
+    for position in ["start", "inversed"] {
+        let board = parse_position(position).unwrap();
+        match database.probe(board) {
+            Ok(value) => println!("{}: {}", position, value.unwrap()),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return 1;
+            }
+        }
+    }
+    0
+}
+
+fn probe(path: &str, options: &Options) -> i32 {
+    let mut database = match Database::open(path) {
+        Ok(database) => database,
+        Err(e) => {
+            eprintln!("error: cannot open {}: {}", path, e);
+            return 1;
+        }
+    };
+    println!("{}", options.position.to_notation());
+    match database.probe(options.position) {
+        Ok(Some(value)) => println!("{} for the side to move", value),
+        Ok(None) => println!("the game has ended"),
+        Err(e) => {
+            eprintln!("error: cannot read {}: {}", path, e);
+            return 1;
+        }
+    }
+    0
+}
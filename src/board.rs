@@ -209,43 +209,10 @@ impl BoardState {
         }
     }
 
-    const CELL_WEIGHTS_PAWN: [[i32; 5]; 5] = [
-        [0, 3, 0, 3, 0],
-        [3, 25, 25, 25, 3],
-        [0, 25, 0, 25, 0],
-        [3, 25, 25, 25, 3],
-        [0, 3, 0, 3, 0],
-    ];
-
-    const CELL_WEIGHTS_KING: [[i32; 5]; 5] = [
-        [10, 0, 10, 0, 10],
-        [0, 50, 50, 50, 0],
-        [10, 50, 0, 50, 10],
-        [0, 50, 50, 50, 0],
-        [10, 0, 10, 0, 10],
-    ];
-
+    /// Static evaluation from White's point of view, by the default
+    /// evaluator as for `Board2::score`.
     pub fn score(&self) -> i32 {
-        if let Some(winner) = self.winner() {
-            return if winner == Player::White {
-                100000
-            } else {
-                -100000
-            };
-        }
-        let mut score = 0;
-        for row in 0..5 {
-            for col in 0..5 {
-                match self[(row, col)] {
-                    WhitePawn => score += Self::CELL_WEIGHTS_PAWN[row][col],
-                    BlackPawn => score -= Self::CELL_WEIGHTS_PAWN[row][col],
-                    WhiteKing => score += Self::CELL_WEIGHTS_KING[row][col],
-                    BlackKing => score -= Self::CELL_WEIGHTS_KING[row][col],
-                    _ => {}
-                };
-            }
-        }
-        score
+        self.to_board2(Player::White).score()
     }
 
     pub fn to_board2(&self, player: Player) -> Board2 {
@@ -5,7 +5,11 @@ use std::{
 
 use serde::Serialize;
 
-use crate::cell::CellState;
+use crate::{
+    cell::CellState,
+    eval::{Evaluator, TableEvaluator},
+    player::Player,
+};
 
 #[derive(Hash, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Board2 {
@@ -27,11 +31,52 @@ impl Debug for Board2 {
     }
 }
 
+/// Why a list of positions does not describe a legal board.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PositionError {
+    /// Expected 4 white pawns, 4 black pawns, both kings and the side to move.
+    WrongArity(usize),
+    OutOfRange {
+        index: usize,
+        square: u8,
+    },
+    DuplicateSquare(u8),
+    PawnOnCentre(usize),
+    BothKingsOnCentre,
+    InvalidSideToMove(u8),
+}
+
+impl std::fmt::Display for PositionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WrongArity(n) => write!(f, "expected 11 values but got {}", n),
+            Self::OutOfRange { index, square } => {
+                write!(f, "value {} at index {} is not a square", square, index)
+            }
+            Self::DuplicateSquare(square) => {
+                write!(f, "more than one piece on square {}", square)
+            }
+            Self::PawnOnCentre(index) => {
+                write!(f, "pawn at index {} stands on the centre square", index)
+            }
+            Self::BothKingsOnCentre => write!(f, "both kings stand on the centre square"),
+            Self::InvalidSideToMove(v) => write!(f, "side to move must be 0 or 1, not {}", v),
+        }
+    }
+}
+
+impl std::error::Error for PositionError {}
+
 impl Board2 {
     pub fn new(data: u64) -> Board2 {
         Board2 { data }
     }
 
+    /// The packed representation taken by `Board2::new`.
+    pub fn data(self) -> u64 {
+        self.data
+    }
+
     pub fn from_positions(pos: &Vec<u8>) -> Board2 {
         let mut data = 0;
         for (i, p) in pos.iter().enumerate() {
@@ -40,6 +85,39 @@ impl Board2 {
         Board2 { data }
     }
 
+    /// Like `from_positions`, but rejects anything that is not a legal board
+    /// and sorts the pawns so equal boards compare equal.
+    pub fn try_from_positions(pos: &[u8]) -> Result<Board2, PositionError> {
+        if pos.len() != 11 {
+            return Err(PositionError::WrongArity(pos.len()));
+        }
+        for (index, &square) in pos[..10].iter().enumerate() {
+            if square >= 25 {
+                return Err(PositionError::OutOfRange { index, square });
+            }
+            if index < 8 && square == 12 {
+                return Err(PositionError::PawnOnCentre(index));
+            }
+        }
+        if pos[8] == 12 && pos[9] == 12 {
+            return Err(PositionError::BothKingsOnCentre);
+        }
+        let mut occupied = 0u32;
+        for &square in pos[..10].iter() {
+            if occupied & (1 << square) != 0 {
+                return Err(PositionError::DuplicateSquare(square));
+            }
+            occupied |= 1 << square;
+        }
+        if pos[10] > 1 {
+            return Err(PositionError::InvalidSideToMove(pos[10]));
+        }
+        let mut pos = pos.to_vec();
+        pos[0..4].sort_unstable();
+        pos[4..8].sort_unstable();
+        Ok(Self::from_positions(&pos))
+    }
+
     pub fn flatten(self) -> [CellState; 25] {
         let mut board = [CellState::Empty; 25];
         board[((self.data >> 0) & 0b11111) as usize] = CellState::WhitePawn;
@@ -116,6 +194,29 @@ impl Board2 {
         (self.data >> 40) & 0b11111 == 12 || (self.data >> 45) & 0b11111 == 12
     }
 
+    /// Whether the game ended with White's king on the centre.
+    pub fn white_won(self) -> bool {
+        self.white_king() == 12
+    }
+
+    /// Squares of White's pawns, in increasing order.
+    pub fn white_pawns(self) -> [u8; 4] {
+        [0, 5, 10, 15].map(|offset| ((self.data >> offset) & 0b11111) as u8)
+    }
+
+    /// Squares of Black's pawns, in increasing order.
+    pub fn black_pawns(self) -> [u8; 4] {
+        [20, 25, 30, 35].map(|offset| ((self.data >> offset) & 0b11111) as u8)
+    }
+
+    pub fn white_king(self) -> u8 {
+        ((self.data >> 40) & 0b11111) as u8
+    }
+
+    pub fn black_king(self) -> u8 {
+        ((self.data >> 45) & 0b11111) as u8
+    }
+
     pub fn do_move(self, m: Move) -> Self {
         let mut data = self.data;
         let to = m.to as u64;
@@ -141,7 +242,19 @@ impl Board2 {
         (self.data >> 50) & 1 != 0
     }
 
+    /// All moves for the side to move. If one of them wins the game, only
+    /// that move is returned.
     pub fn all_moves(self) -> Vec<(Move, Board2)> {
+        self.generate_moves(true)
+    }
+
+    /// Every legal move for the side to move, including the ones that do not
+    /// win when a winning move is available.
+    pub fn legal_moves(self) -> Vec<(Move, Board2)> {
+        self.generate_moves(false)
+    }
+
+    fn generate_moves(self, stop_at_win: bool) -> Vec<(Move, Board2)> {
         if self.ended() {
             return Vec::new();
         }
@@ -155,24 +268,12 @@ impl Board2 {
         for i in offsets {
             let from = (self.data >> i) & 0b11111;
             for ray in &MOVEMENT_TABLE[from as usize] {
-                let to = if ray[0] == x {
+                if ray[0] == x {
                     break; // no more directions
-                } else if flat & (1 << ray[0]) != 0 {
-                    continue; // first cell blocked
-                } else if ray[1] == x {
-                    ray[0]
-                } else if flat & (1 << ray[1]) != 0 {
-                    ray[0]
-                } else if ray[2] == x {
-                    ray[1]
-                } else if flat & (1 << ray[2]) != 0 {
-                    ray[1]
-                } else if ray[3] == x {
-                    ray[2]
-                } else if flat & (1 << ray[3]) != 0 {
-                    ray[2]
-                } else {
-                    ray[3]
+                }
+                let to = match slide(flat, ray) {
+                    Some(to) => to,
+                    None => continue, // first cell blocked
                 };
                 if i < 40 && to == 12 {
                     continue;
@@ -183,7 +284,7 @@ impl Board2 {
                     to,
                 };
                 let board = self.do_move(m);
-                if board.ended() {
+                if stop_at_win && board.ended() {
                     return vec![(m, board)];
                 }
                 moves.push((m, board));
@@ -192,44 +293,71 @@ impl Board2 {
         moves
     }
 
-    const CELL_WEIGHTS_PAWN: [i32; 25] = [
-        0, 3, 0, 3, 0, //
-        3, 25, 25, 25, 3, //
-        0, 25, 0, 25, 0, //
-        3, 25, 25, 25, 3, //
-        0, 3, 0, 3, 0,
-    ];
+    /// Squares a piece on `from` can slide to, as a bit array like
+    /// `flatten_to_bitarray`. The centre is included for pawns too, although
+    /// they may not stop there.
+    pub fn destinations(self, from: u8) -> u32 {
+        destinations(self.flatten_to_bitarray(), from)
+    }
 
-    const CELL_WEIGHTS_KING: [i32; 25] = [
-        10, 0, 10, 0, 10, //
-        0, 200, 200, 200, 0, //
-        10, 200, 100000, 200, 10, //
-        0, 200, 200, 200, 0, //
-        10, 0, 10, 0, 10,
-    ];
+    /// `destinations` of every piece, in the order of the packing: White's
+    /// pawns, Black's pawns, White's king and Black's king.
+    pub fn all_destinations(self) -> [u32; 10] {
+        let flat = self.flatten_to_bitarray();
+        let mut all = [0; 10];
+        for (i, squares) in all.iter_mut().enumerate() {
+            *squares = destinations(flat, ((self.data >> (5 * i)) & 0b11111) as u8);
+        }
+        all
+    }
 
-    pub fn score(&self) -> i32 {
-        let mut score = 0;
-        let data = self.data;
-        let white_king_pos = (data >> 40) & 0b11111;
-        let black_king_pos = (data >> 45) & 0b11111;
-        if white_king_pos == 12 {
-            return 100000;
+    /// The ray along which the king of `player` can slide onto the centre,
+    /// from the square next to the king to the centre, as a bit array like
+    /// `flatten_to_bitarray`; 0 if it cannot. There is at most one such ray,
+    /// as only one line leads from the king to the centre.
+    pub fn threats(self, player: Player) -> u32 {
+        if self.ended() {
+            return 0;
         }
-        if black_king_pos == 12 {
-            return -100000;
+        let king = match player {
+            Player::White => self.white_king(),
+            Player::Black => self.black_king(),
+        };
+        let flat = self.flatten_to_bitarray();
+        MOVEMENT_TABLE[king as usize]
+            .iter()
+            .take_while(|ray| ray[0] != x)
+            .find(|ray| slide(flat, ray) == Some(12))
+            .map_or(0, |ray| {
+                ray.iter()
+                    .take_while(|&&square| square != 12)
+                    .fold(1 << 12, |squares, &square| squares | 1 << square)
+            })
+    }
+
+    /// Whether the side to move must stop the other king from reaching the
+    /// centre on its next move.
+    pub fn is_threatened(self) -> bool {
+        let opponent = if self.maximizing() {
+            Player::Black
+        } else {
+            Player::White
+        };
+        self.threats(opponent) != 0
+    }
+
+    /// Static evaluation from White's point of view by the default
+    /// evaluator, `eval::TableEvaluator`; `WIN_SCORE` or `-WIN_SCORE` once a
+    /// king reached the centre.
+    pub fn score(&self) -> i32 {
+        if self.ended() {
+            return if self.white_won() {
+                WIN_SCORE
+            } else {
+                -WIN_SCORE
+            };
         }
-        score += Self::CELL_WEIGHTS_KING[white_king_pos as usize];
-        score -= Self::CELL_WEIGHTS_KING[black_king_pos as usize];
-        score += Self::CELL_WEIGHTS_PAWN[((data >> 0) & 0b11111) as usize];
-        score += Self::CELL_WEIGHTS_PAWN[((data >> 5) & 0b11111) as usize];
-        score += Self::CELL_WEIGHTS_PAWN[((data >> 10) & 0b11111) as usize];
-        score += Self::CELL_WEIGHTS_PAWN[((data >> 15) & 0b11111) as usize];
-        score -= Self::CELL_WEIGHTS_PAWN[((data >> 20) & 0b11111) as usize];
-        score -= Self::CELL_WEIGHTS_PAWN[((data >> 25) & 0b11111) as usize];
-        score -= Self::CELL_WEIGHTS_PAWN[((data >> 30) & 0b11111) as usize];
-        score -= Self::CELL_WEIGHTS_PAWN[((data >> 35) & 0b11111) as usize];
-        score
+        TableEvaluator::DEFAULT.evaluate(*self)
     }
 
     pub fn new_original() -> Self {
@@ -276,6 +404,10 @@ impl Board2 {
     }
 }
 
+/// Score of a won game. Search scores subtract the number of plies until the
+/// win, see `win_score` in the crate root.
+pub const WIN_SCORE: i32 = 100000;
+
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize)]
 pub struct Move {
     from_offset: u8,
@@ -291,6 +423,29 @@ impl Move {
             to,
         }
     }
+
+    /// The piece that moves.
+    pub fn piece(&self) -> CellState {
+        match self.from_offset {
+            0..=15 => CellState::WhitePawn,
+            20..=35 => CellState::BlackPawn,
+            40 => CellState::WhiteKing,
+            _ => CellState::BlackKing,
+        }
+    }
+
+    /// Offset in the packed board of the piece that moves.
+    pub fn from_offset(&self) -> u8 {
+        self.from_offset
+    }
+
+    pub fn from(&self) -> u8 {
+        self.from
+    }
+
+    pub fn to(&self) -> u8 {
+        self.to
+    }
 }
 
 impl Debug for Move {
@@ -311,6 +466,27 @@ const x: u8 = 0xff;
 const z: [u8; 4] = [0xff; 4];
 const nil2: [[u8; 4]; 8] = [[0xff; 4]; 8];
 
+/// Where a piece sliding along `ray` stops on a board occupied as `flat`,
+/// or `None` if the first cell is blocked.
+fn slide(flat: u32, ray: &[u8; 4]) -> Option<u8> {
+    let mut to = None;
+    for &square in ray {
+        if square == x || flat & (1 << square) != 0 {
+            break;
+        }
+        to = Some(square);
+    }
+    to
+}
+
+pub(crate) fn destinations(flat: u32, from: u8) -> u32 {
+    MOVEMENT_TABLE[from as usize]
+        .iter()
+        .take_while(|ray| ray[0] != x)
+        .filter_map(|ray| slide(flat, ray))
+        .fold(0, |squares, to| squares | 1 << to)
+}
+
 const MOVEMENT_TABLE: [[[u8; 4]; 8]; 32] = [
     [
         [1, 2, 3, 4],
@@ -568,8 +744,89 @@ fn test_all_moves_when_almost_win() {
     println!("{:?}", board.all_moves());
 }
 
+#[test]
+fn test_try_from_positions() {
+    assert_eq!(
+        Board2::try_from_positions(&[0, 1, 3, 4, 20, 21, 23, 24, 2, 22, 1]),
+        Ok(Board2::new_original())
+    );
+    assert_eq!(
+        Board2::try_from_positions(&[4, 3, 1, 0, 24, 23, 21, 20, 2, 22, 1]),
+        Ok(Board2::new_original())
+    );
+    assert_eq!(
+        Board2::try_from_positions(&[0, 1, 3, 4, 20, 21, 23, 24, 2, 22]),
+        Err(PositionError::WrongArity(10))
+    );
+    assert_eq!(
+        Board2::try_from_positions(&[0, 1, 3, 4, 20, 21, 23, 25, 2, 22, 1]),
+        Err(PositionError::OutOfRange {
+            index: 7,
+            square: 25
+        })
+    );
+    assert_eq!(
+        Board2::try_from_positions(&[0, 1, 3, 4, 20, 21, 23, 4, 2, 22, 1]),
+        Err(PositionError::DuplicateSquare(4))
+    );
+    assert_eq!(
+        Board2::try_from_positions(&[0, 1, 3, 12, 20, 21, 23, 24, 2, 22, 1]),
+        Err(PositionError::PawnOnCentre(3))
+    );
+    assert_eq!(
+        Board2::try_from_positions(&[0, 1, 3, 4, 20, 21, 23, 24, 12, 12, 1]),
+        Err(PositionError::BothKingsOnCentre)
+    );
+    assert_eq!(
+        Board2::try_from_positions(&[0, 1, 3, 4, 20, 21, 23, 24, 2, 22, 2]),
+        Err(PositionError::InvalidSideToMove(2))
+    );
+}
+
 #[test]
 fn test_ended() {
     let board = Board2::from_positions(&vec![3, 4, 7, 16, 13, 17, 18, 24, 12, 0, 0]);
     assert!(board.ended());
 }
+
+#[test]
+fn test_destinations() {
+    let board = Board2::new_original();
+    assert_eq!(board.white_king(), 2);
+    assert_eq!(board.destinations(2), 1 << 10 | 1 << 14 | 1 << 17);
+    // Every legal move ends on one of the destinations of its piece.
+    let board = Board2::parse_notation("1P1PP/1p3/5/P1K2/1pkpp w").unwrap();
+    for (m, _) in board.legal_moves() {
+        assert_ne!(board.destinations(m.from()) & 1 << m.to(), 0);
+    }
+    let pawn_moves: u32 = board
+        .white_pawns()
+        .iter()
+        .map(|&square| (board.destinations(square) & !(1 << 12)).count_ones())
+        .sum();
+    let king_moves = board.destinations(board.white_king()).count_ones();
+    assert_eq!(
+        (pawn_moves + king_moves) as usize,
+        board.legal_moves().len()
+    );
+    let all = board.all_destinations();
+    assert_eq!(all[0], board.destinations(board.white_pawns()[0]));
+    assert_eq!(all[9], board.destinations(board.black_king()));
+}
+
+#[test]
+fn test_threats() {
+    // White's king on c1 can slide onto the centre against the pawn on c4.
+    let board = Board2::parse_notation("PPK2/P3P/5/2p2/ppk1p b").unwrap();
+    assert_eq!(board.threats(Player::White), 1 << 7 | 1 << 12);
+    assert_eq!(board.threats(Player::Black), 0);
+    assert!(board.is_threatened());
+    // Moving lets White win at once, so it is no threat to White.
+    let board = Board2::parse_notation("PPK2/P3P/5/2p2/ppk1p w").unwrap();
+    assert!(!board.is_threatened());
+    assert!(board.all_moves()[0].1.ended());
+    // Without the stopper, the king slides past the centre.
+    let board = Board2::parse_notation("PPK2/P3P/5/p4/pk1pp b").unwrap();
+    assert_eq!(board.threats(Player::White), 0);
+    assert!(!Board2::new_original().is_threatened());
+}
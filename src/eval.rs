@@ -0,0 +1,337 @@
+//! Static evaluation of positions.
+//!
+//! The search is generic over an `Evaluator`, so that other evaluations,
+//! such as weights loaded from a file or a learned model, can be plugged in
+//! without changing it. `TableEvaluator` is the default: every piece scores
+//! a bonus depending on its square, and each side scores for the freedom of
+//! its king and its pieces.
+//!
+//! A king wins by sliding exactly onto the centre, which it can only do
+//! along a line that ends in a piece next to the centre, a stopper. Besides
+//! the tables, a side therefore scores for each move of its king, for a king
+//! with an open ray to the centre, for each stopper whose line its king can
+//! step onto next move, and for each move of its pieces.
+//!
+//! The weights of a `TableEvaluator` can be loaded at runtime from a JSON
+//! file such as
+//!
+//! ```text
+//! {
+//!   "king": [
+//!     10, 0, 10, 0, 10,
+//!     0, 200, 200, 200, 0,
+//!     10, 200, 0, 200, 10,
+//!     0, 200, 200, 200, 0,
+//!     10, 0, 10, 0, 10
+//!   ]
+//! }
+//! ```
+//!
+//! with one weight per square, row by row starting from White's home row.
+//! Missing tables keep their default weights, so a file only needs the ones
+//! it changes; `penguin weights` prints all of them, and `penguin tune`
+//! fits them to self-play games (see `crate::tune`). Weights with which an
+//! evaluation could exceed `MAX_EVALUATION` are rejected.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    fs,
+    path::Path,
+};
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{board2::Board2, MIN_WIN_SCORE};
+
+pub trait Evaluator: Sync {
+    /// Evaluates `board`, a position that has not ended, from White's point
+    /// of view. Scores must stay well below `MIN_WIN_SCORE`, above which
+    /// they are taken for won games.
+    fn evaluate(&self, board: Board2) -> i32;
+}
+
+impl<E: Evaluator + ?Sized> Evaluator for Box<E> {
+    fn evaluate(&self, board: Board2) -> i32 {
+        (**self).evaluate(board)
+    }
+}
+
+/// Piece-square tables and mobility terms, counted positively for White
+/// and negatively for Black.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TableEvaluator {
+    pub pawn: [i32; 25],
+    pub king: [i32; 25],
+    /// Per move of the king.
+    pub king_moves: i32,
+    /// For a king that can slide onto the centre: it wins unless the other
+    /// side blocks it, or wins first.
+    pub king_ray: i32,
+    /// Per stopper the king can line up with in one move.
+    pub stopper: i32,
+    /// Per move of any piece, pawn moves to the centre excepted.
+    pub mobility: i32,
+}
+
+/// Squares next to the centre with, for a king stopping against a piece
+/// there, the squares it comes from: the opposite square next to the centre
+/// and the one beyond it.
+const STOPPERS: [(u8, u8, u8); 8] = [
+    (6, 18, 24),
+    (7, 17, 22),
+    (8, 16, 20),
+    (11, 13, 14),
+    (13, 11, 10),
+    (16, 8, 4),
+    (17, 7, 2),
+    (18, 6, 0),
+];
+
+const CENTRE: u32 = 1 << 12;
+
+/// Bound of the evaluation of a `TableEvaluator` in either direction, far
+/// below the scores of won games.
+pub const MAX_EVALUATION: i32 = MIN_WIN_SCORE / 3;
+
+/// Most moves a piece can have, one per direction.
+const MAX_PIECE_MOVES: i64 = 8;
+
+impl TableEvaluator {
+    pub const DEFAULT: TableEvaluator = TableEvaluator {
+        pawn: [
+            0, 3, 0, 3, 0, //
+            3, 25, 25, 25, 3, //
+            0, 25, 0, 25, 0, //
+            3, 25, 25, 25, 3, //
+            0, 3, 0, 3, 0,
+        ],
+        // The centre is never scored: a king there has won.
+        king: [
+            10, 0, 10, 0, 10, //
+            0, 200, 200, 200, 0, //
+            10, 200, 0, 200, 10, //
+            0, 200, 200, 200, 0, //
+            10, 0, 10, 0, 10,
+        ],
+        king_moves: 4,
+        king_ray: 60,
+        stopper: 20,
+        mobility: 2,
+    };
+
+    /// Parses weights in the JSON form described in the module
+    /// documentation.
+    pub fn from_json(s: &str) -> Result<TableEvaluator, WeightsError> {
+        let weights: TableEvaluator = serde_json::from_str(s).map_err(WeightsError::Json)?;
+        let bound = weights.max_evaluation();
+        if bound > MAX_EVALUATION as i64 {
+            return Err(WeightsError::TooLarge { bound });
+        }
+        Ok(weights)
+    }
+
+    /// Bound of the evaluation in either direction with these weights: the
+    /// most one side can score, as the other side scores at least minus
+    /// that.
+    pub fn max_evaluation(&self) -> i64 {
+        let largest = |table: &[i32; 25]| {
+            let mut weights = table.map(|weight| (weight as i64).abs());
+            weights.sort_unstable();
+            weights
+        };
+        let pawns: i64 = largest(&self.pawn)[21..].iter().sum();
+        let king = largest(&self.king)[24];
+        let king_moves = (self.king_moves as i64).abs() * MAX_PIECE_MOVES;
+        let king_ray = (self.king_ray as i64).abs();
+        let stoppers = (self.stopper as i64).abs() * STOPPERS.len() as i64;
+        let mobility = (self.mobility as i64).abs() * 5 * MAX_PIECE_MOVES;
+        2 * (pawns + king + king_moves + king_ray + stoppers + mobility)
+    }
+
+    /// The weights in JSON, with every table laid out as a board.
+    pub fn to_json(&self) -> String {
+        let value = serde_json::to_value(self).unwrap();
+        let fields = value.as_object().unwrap().iter().map(|(name, value)| {
+            let value = match value.as_array() {
+                Some(squares) if squares.len() == 25 => {
+                    let rows = squares
+                        .chunks(5)
+                        .map(|row| format!("    {}", row.iter().join(", ")));
+                    format!("[\n{}\n  ]", rows.format(",\n"))
+                }
+                _ => value.to_string(),
+            };
+            format!("  {:?}: {}", name, value)
+        });
+        format!("{{\n{}\n}}", fields.format(",\n"))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<TableEvaluator, WeightsError> {
+        let s = fs::read_to_string(path).map_err(WeightsError::Io)?;
+        Self::from_json(&s)
+    }
+}
+
+#[derive(Debug)]
+pub enum WeightsError {
+    Json(serde_json::Error),
+    Io(std::io::Error),
+    /// The evaluation could reach `bound`, above `MAX_EVALUATION`.
+    TooLarge {
+        bound: i64,
+    },
+}
+
+impl Display for WeightsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json(e) => write!(f, "invalid weights: {}", e),
+            Self::Io(e) => write!(f, "cannot read weights: {}", e),
+            Self::TooLarge { bound } => write!(
+                f,
+                "weights too large: evaluations could reach {}, above {}",
+                bound, MAX_EVALUATION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WeightsError {}
+
+impl Default for TableEvaluator {
+    fn default() -> TableEvaluator {
+        TableEvaluator::DEFAULT
+    }
+}
+
+impl TableEvaluator {
+    /// The terms scored by the side whose king is on `king` and pawns on
+    /// `pawns`. `moves` are the destinations of its pawns then its king,
+    /// only computed if `mobility` is weighted.
+    fn side(&self, board: Board2, king: u8, pawns: [u8; 4], moves: Option<[u32; 5]>) -> i32 {
+        let flat = board.flatten_to_bitarray();
+        let king_moves = match moves {
+            Some(moves) => moves[4],
+            None => board.destinations(king),
+        };
+        let mut score = self.king[king as usize] + self.king_moves * king_moves.count_ones() as i32;
+        if king_moves & CENTRE != 0 {
+            score += self.king_ray;
+        }
+        let stoppers = STOPPERS
+            .iter()
+            .filter(|&&(stopper, next, beyond)| {
+                flat & 1 << stopper != 0
+                    && flat & 1 << next == 0
+                    && king_moves & (1 << next | 1 << beyond) != 0
+            })
+            .count();
+        score += self.stopper * stoppers as i32;
+        for square in pawns {
+            score += self.pawn[square as usize];
+        }
+        if let Some(moves) = moves {
+            let pawn_moves: u32 = moves[..4]
+                .iter()
+                .map(|squares| (squares & !CENTRE).count_ones())
+                .sum();
+            score += self.mobility * (pawn_moves + king_moves.count_ones()) as i32;
+        }
+        score
+    }
+}
+
+impl Evaluator for TableEvaluator {
+    fn evaluate(&self, board: Board2) -> i32 {
+        let (white_moves, black_moves) = if self.mobility != 0 {
+            let all = board.all_destinations();
+            let white = [all[0], all[1], all[2], all[3], all[8]];
+            let black = [all[4], all[5], all[6], all[7], all[9]];
+            (Some(white), Some(black))
+        } else {
+            (None, None)
+        };
+        self.side(board, board.white_king(), board.white_pawns(), white_moves)
+            - self.side(board, board.black_king(), board.black_pawns(), black_moves)
+    }
+}
+
+#[test]
+fn test_table_evaluator() {
+    let evaluator = TableEvaluator::default();
+    // The starting positions are symmetric.
+    assert_eq!(evaluator.evaluate(Board2::new_original()), 0);
+    assert_eq!(evaluator.evaluate(Board2::new_with_king_inversed()), 0);
+
+    let board = Board2::parse_notation("1P1PP/1p3/5/P1K2/1pkpp w").unwrap();
+    assert_eq!(evaluator.evaluate(board), board.score());
+    assert_eq!(
+        evaluator.evaluate(board),
+        evaluator.evaluate(board.reflect())
+    );
+}
+
+#[test]
+fn test_weights_json() {
+    let default = TableEvaluator::default();
+    assert_eq!(
+        TableEvaluator::from_json(&default.to_json()).unwrap(),
+        default
+    );
+
+    // Tables missing from the file keep their defaults.
+    let king =
+        r#"{"king": [1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1]}"#;
+    let weights = TableEvaluator::from_json(king).unwrap();
+    assert_eq!(weights.king, [1; 25]);
+    assert_eq!(weights.pawn, default.pawn);
+
+    assert!(TableEvaluator::from_json(r#"{"pawns": []}"#).is_err());
+    assert!(TableEvaluator::from_json(r#"{"king": [1, 2]}"#).is_err());
+}
+
+#[test]
+fn test_max_evaluation() {
+    let default = TableEvaluator::default();
+    assert!(default.max_evaluation() <= MAX_EVALUATION as i64);
+    for _ in 0..200 {
+        let (state, player) = crate::board::BoardState::random();
+        let board = state.to_board2(player);
+        if !board.ended() {
+            assert!((default.evaluate(board) as i64).abs() <= default.max_evaluation());
+        }
+    }
+
+    // Every weight is small, but a position with many moves would score
+    // like a won game.
+    let mobility = TableEvaluator::from_json(r#"{"mobility": 1000}"#);
+    assert!(matches!(
+        mobility,
+        Err(WeightsError::TooLarge { bound }) if bound > MAX_EVALUATION as i64
+    ));
+    assert!(TableEvaluator::from_json(r#"{"mobility": 100}"#).is_ok());
+}
+
+#[test]
+fn test_king_terms() {
+    let only = |king_moves, king_ray, stopper| TableEvaluator {
+        pawn: [0; 25],
+        king: [0; 25],
+        king_moves,
+        king_ray,
+        stopper,
+        mobility: 0,
+    };
+    // White's king can slide onto the centre against the pawn on c4 and has
+    // four moves, Black's king three.
+    let board = Board2::parse_notation("PPK2/P3P/5/2p2/ppk1p w").unwrap();
+    assert_eq!(only(0, 1, 0).evaluate(board), 1);
+    assert_eq!(only(1, 0, 0).evaluate(board), 1);
+    assert_eq!(only(0, 0, 1).evaluate(board), 0);
+    // From e1, White's king can line up with that pawn by moving to c1.
+    let board = Board2::parse_notation("PP2K/P3P/5/2p2/ppk1p w").unwrap();
+    assert_eq!(only(0, 1, 0).evaluate(board), 0);
+    assert_eq!(only(0, 0, 1).evaluate(board), 1);
+}
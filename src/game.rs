@@ -0,0 +1,443 @@
+//! A full game: start position, moves, result and metadata, with a JSON
+//! record format that can be saved, loaded back and replayed.
+//!
+//! A record looks like
+//!
+//! ```text
+//! {
+//!   "white": "alice",
+//!   "black": "penguin",
+//!   "date": "2023-07-14",
+//!   "variant": "king_inversed",
+//!   "start": "PPkPP/5/5/5/ppKpp w",
+//!   "moves": ["c5-c2", "a5-a2"],
+//!   "result": "ongoing"
+//! }
+//! ```
+//!
+//! Positions and moves use the notation described in `notation`. Every move
+//! is checked against the legal moves of the position it is played in when a
+//! record is loaded. Records may also carry a `"repetition_rule"`, see
+//! `RepetitionRule`.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    fs,
+    path::Path,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    board2::{Board2, Move},
+    notation::{MoveError, NotationError},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Variant {
+    #[default]
+    Original,
+    KingInversed,
+    /// Any other start position.
+    Custom,
+}
+
+impl Variant {
+    pub fn of(start: Board2) -> Variant {
+        if start == Board2::new_original() {
+            Variant::Original
+        } else if start == Board2::new_with_king_inversed() {
+            Variant::KingInversed
+        } else {
+            Variant::Custom
+        }
+    }
+}
+
+/// How the game treats a move that recreates an earlier position.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepetitionRule {
+    /// Such moves are illegal, and a player left without legal moves loses.
+    #[default]
+    Forbidden,
+    /// The game is drawn once a position occurs for the third time.
+    DrawOnThreefold,
+    /// The player who recreates an earlier position loses.
+    LossForRepeater,
+}
+
+/// What a single move amounts to under a `RepetitionRule`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Repetition {
+    Allowed,
+    Illegal,
+    Draw,
+    LossForMover,
+}
+
+impl RepetitionRule {
+    /// Judges a move to a position that has already occurred `occurrences`
+    /// times in the game.
+    pub fn judge(self, occurrences: usize) -> Repetition {
+        match self {
+            _ if occurrences == 0 => Repetition::Allowed,
+            Self::Forbidden => Repetition::Illegal,
+            Self::DrawOnThreefold if occurrences >= 2 => Repetition::Draw,
+            Self::DrawOnThreefold => Repetition::Allowed,
+            Self::LossForRepeater => Repetition::LossForMover,
+        }
+    }
+}
+
+impl FromStr for RepetitionRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "forbidden" => Ok(Self::Forbidden),
+            "draw" | "draw_on_threefold" => Ok(Self::DrawOnThreefold),
+            "loss" | "loss_for_repeater" => Ok(Self::LossForRepeater),
+            _ => Err(format!("unknown repetition rule `{}`", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameResult {
+    #[default]
+    Ongoing,
+    WhiteWins,
+    BlackWins,
+    Draw,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameMetadata {
+    #[serde(default)]
+    pub white: String,
+    #[serde(default)]
+    pub black: String,
+    #[serde(default)]
+    pub date: String,
+    #[serde(default)]
+    pub variant: Variant,
+    #[serde(default)]
+    pub repetition_rule: RepetitionRule,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GameRecord {
+    #[serde(flatten)]
+    metadata: GameMetadata,
+    start: String,
+    moves: Vec<String>,
+    #[serde(default)]
+    result: GameResult,
+}
+
+#[derive(Debug)]
+pub enum GameError {
+    InvalidStart(NotationError),
+    IllegalMove {
+        ply: usize,
+        error: MoveError,
+    },
+    RepeatedPosition {
+        ply: usize,
+    },
+    GameOver,
+    ResultMismatch {
+        recorded: GameResult,
+        actual: GameResult,
+    },
+    Json(serde_json::Error),
+    Io(std::io::Error),
+}
+
+impl Display for GameError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidStart(e) => write!(f, "invalid start position: {}", e),
+            Self::IllegalMove { ply, error } => write!(f, "ply {}: {}", ply + 1, error),
+            Self::RepeatedPosition { ply } => write!(
+                f,
+                "ply {}: repeating an earlier position is forbidden",
+                ply + 1
+            ),
+            Self::GameOver => write!(f, "the game is already over"),
+            Self::ResultMismatch { recorded, actual } => write!(
+                f,
+                "recorded result {:?} does not match the final position ({:?})",
+                recorded, actual
+            ),
+            Self::Json(e) => write!(f, "{}", e),
+            Self::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for GameError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Game {
+    pub metadata: GameMetadata,
+    moves: Vec<Move>,
+    /// The start position followed by the position after every move.
+    positions: Vec<Board2>,
+    result: GameResult,
+}
+
+impl Game {
+    pub fn new(start: Board2) -> Game {
+        Self::with_repetition_rule(start, RepetitionRule::default())
+    }
+
+    pub fn with_repetition_rule(start: Board2, rule: RepetitionRule) -> Game {
+        let mut game = Game {
+            metadata: GameMetadata {
+                variant: Variant::of(start),
+                repetition_rule: rule,
+                ..GameMetadata::default()
+            },
+            moves: vec![],
+            positions: vec![start],
+            result: GameResult::Ongoing,
+        };
+        game.update_result(Repetition::Allowed);
+        game
+    }
+
+    fn win_for(white: bool) -> GameResult {
+        if white {
+            GameResult::WhiteWins
+        } else {
+            GameResult::BlackWins
+        }
+    }
+
+    fn update_result(&mut self, last_move: Repetition) {
+        let board = self.current();
+        self.result = if board.ended() {
+            Self::win_for(board.white_won())
+        } else if last_move == Repetition::Draw {
+            GameResult::Draw
+        } else if last_move == Repetition::LossForMover {
+            // The player who just moved is the one not to move now.
+            Self::win_for(board.maximizing())
+        } else if self.legal_moves().is_empty() {
+            Self::win_for(!board.maximizing())
+        } else {
+            GameResult::Ongoing
+        };
+    }
+
+    pub fn repetition_rule(&self) -> RepetitionRule {
+        self.metadata.repetition_rule
+    }
+
+    /// How many times `board` has occurred in the game so far.
+    pub fn occurrences(&self, board: Board2) -> usize {
+        self.positions.iter().filter(|b| **b == board).count()
+    }
+
+    /// The legal moves in the current position, leaving out the ones the
+    /// repetition rule forbids.
+    pub fn legal_moves(&self) -> Vec<(Move, Board2)> {
+        let rule = self.repetition_rule();
+        self.current()
+            .legal_moves()
+            .into_iter()
+            .filter(|(_, next)| rule.judge(self.occurrences(*next)) != Repetition::Illegal)
+            .collect()
+    }
+
+    pub fn start(&self) -> Board2 {
+        self.positions[0]
+    }
+
+    pub fn current(&self) -> Board2 {
+        *self.positions.last().unwrap()
+    }
+
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    /// The start position followed by the position after every move.
+    pub fn positions(&self) -> &[Board2] {
+        &self.positions
+    }
+
+    pub fn result(&self) -> GameResult {
+        self.result
+    }
+
+    /// Records a result the board cannot tell by itself, such as a
+    /// resignation or an adjudication.
+    pub fn set_result(&mut self, result: GameResult) {
+        self.result = result;
+    }
+
+    pub fn play(&mut self, m: Move) -> Result<(), GameError> {
+        if self.result != GameResult::Ongoing {
+            return Err(GameError::GameOver);
+        }
+        let board = self.current();
+        let next = board
+            .legal_moves()
+            .into_iter()
+            .find(|(legal, _)| *legal == m)
+            .map(|(_, next)| next)
+            .ok_or(GameError::IllegalMove {
+                ply: self.moves.len(),
+                error: MoveError::Illegal {
+                    from: m.from(),
+                    to: m.to(),
+                },
+            })?;
+        let repetition = self.repetition_rule().judge(self.occurrences(next));
+        if repetition == Repetition::Illegal {
+            return Err(GameError::RepeatedPosition {
+                ply: self.moves.len(),
+            });
+        }
+        self.moves.push(m);
+        self.positions.push(next);
+        self.update_result(repetition);
+        Ok(())
+    }
+
+    pub fn play_notation(&mut self, s: &str) -> Result<(), GameError> {
+        let m = self
+            .current()
+            .parse_move(s)
+            .map_err(|error| GameError::IllegalMove {
+                ply: self.moves.len(),
+                error,
+            })?;
+        self.play(m)
+    }
+
+    /// The position after `ply` moves, if the game is that long.
+    pub fn position_at(&self, ply: usize) -> Option<Board2> {
+        self.positions.get(ply).copied()
+    }
+
+    /// The same game cut off after `ply` moves.
+    pub fn replay(&self, ply: usize) -> Game {
+        let mut game = Game::with_repetition_rule(self.start(), self.repetition_rule());
+        game.metadata = self.metadata.clone();
+        for m in self.moves.iter().take(ply) {
+            game.play(*m).unwrap();
+        }
+        if ply >= self.moves.len() {
+            game.result = self.result;
+        }
+        game
+    }
+
+    pub fn to_record(&self) -> String {
+        let record = GameRecord {
+            metadata: self.metadata.clone(),
+            start: self.start().to_notation(),
+            moves: self.moves.iter().map(|m| m.to_notation()).collect(),
+            result: self.result,
+        };
+        serde_json::to_string_pretty(&record).unwrap()
+    }
+
+    pub fn from_record(s: &str) -> Result<Game, GameError> {
+        let record: GameRecord = serde_json::from_str(s).map_err(GameError::Json)?;
+        let start = Board2::parse_notation(&record.start).map_err(GameError::InvalidStart)?;
+        let mut game = Game::with_repetition_rule(start, record.metadata.repetition_rule);
+        game.metadata = record.metadata;
+        for m in record.moves.iter() {
+            game.play_notation(m)?;
+        }
+        if game.result != GameResult::Ongoing && game.result != record.result {
+            return Err(GameError::ResultMismatch {
+                recorded: record.result,
+                actual: game.result,
+            });
+        }
+        game.result = record.result;
+        Ok(game)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), GameError> {
+        fs::write(path, self.to_record()).map_err(GameError::Io)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Game, GameError> {
+        let s = fs::read_to_string(path).map_err(GameError::Io)?;
+        Self::from_record(&s)
+    }
+}
+
+#[test]
+fn test_game_record_round_trip() {
+    let mut game = Game::new(Board2::new_with_king_inversed());
+    game.metadata.white = "alice".to_string();
+    for m in ["c5-c2", "a5-a2", "a1-d4"] {
+        game.play_notation(m).unwrap();
+    }
+    let record = game.to_record();
+    let loaded = Game::from_record(&record).unwrap();
+    assert_eq!(loaded, game);
+    assert_eq!(loaded.metadata.variant, Variant::KingInversed);
+    assert_eq!(
+        loaded.position_at(1),
+        Some(Board2::new_with_king_inversed().do_move(game.moves()[0]))
+    );
+    assert_eq!(loaded.replay(1).current(), loaded.position_at(1).unwrap());
+}
+
+#[test]
+fn test_game_record_rejects_illegal_moves() {
+    let record = r#"{"start": "PPKPP/5/5/5/ppkpp w", "moves": ["c1-c4", "c1-c2"]}"#;
+    match Game::from_record(record) {
+        Err(GameError::IllegalMove { ply: 1, .. }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_repetition_rules() {
+    let shuffle = ["a1-d4", "a5-d2", "d4-a1", "d2-a5"];
+    let play = |rule| {
+        let mut game = Game::with_repetition_rule(Board2::new_original(), rule);
+        let mut error = None;
+        for m in shuffle.iter().chain(shuffle.iter()) {
+            if let Err(e) = game.play_notation(m) {
+                error = Some(e);
+                break;
+            }
+        }
+        (game, error)
+    };
+
+    let (game, error) = play(RepetitionRule::Forbidden);
+    assert!(matches!(
+        error,
+        Some(GameError::RepeatedPosition { ply: 3 })
+    ));
+    assert_eq!(game.result(), GameResult::Ongoing);
+    assert_eq!(
+        game.legal_moves().len(),
+        game.current().legal_moves().len() - 1
+    );
+
+    let (game, error) = play(RepetitionRule::LossForRepeater);
+    assert!(matches!(error, Some(GameError::GameOver)));
+    assert_eq!(game.moves().len(), 4);
+    assert_eq!(game.result(), GameResult::WhiteWins);
+
+    let (game, error) = play(RepetitionRule::DrawOnThreefold);
+    assert!(error.is_none());
+    assert_eq!(game.moves().len(), 8);
+    assert_eq!(game.result(), GameResult::Draw);
+}
@@ -1,13 +1,30 @@
 pub mod board;
-mod board2;
+pub mod board2;
 pub mod cell;
+pub mod eval;
+pub mod game;
+pub mod notation;
+mod ordering;
+pub mod perft;
 pub mod player;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod protocol;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod solve;
+pub mod tt;
+pub mod tune;
+#[cfg(all(target_arch = "wasm32", feature = "wasm-threads"))]
+pub mod wasm_threads;
 
-use board2::{Board2, Move};
+use board2::{Board2, Move, PositionError};
+use eval::{Evaluator, TableEvaluator};
+use game::{Repetition, RepetitionRule};
 use itertools::Itertools;
-use serde::Serialize;
+use ordering::MoveOrdering;
+use player::Player;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashMap,
     fmt::Debug,
     rc::Rc,
     sync::{
@@ -15,6 +32,7 @@ use std::{
         Arc,
     },
 };
+use tt::{Bound, TranspositionTable};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_test::wasm_bindgen_test;
 
@@ -25,6 +43,22 @@ pub struct SearchResult {
     pub first_move_scores: Vec<(Move, i32)>,
 }
 
+impl SearchResult {
+    fn leaf(score: i32) -> SearchResult {
+        SearchResult {
+            score,
+            best_path: vec![],
+            first_move_scores: vec![],
+        }
+    }
+
+    /// The opponent's reply to the best move expected by the principal
+    /// variation, on which to ponder once the best move is played.
+    pub fn expected_reply(&self) -> Option<Move> {
+        self.best_path.iter().rev().nth(1).copied()
+    }
+}
+
 impl Debug for SearchResult {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?}\n", self.score)?;
@@ -35,34 +69,344 @@ impl Debug for SearchResult {
     }
 }
 
-struct SearchState {
-    transposition_table: HashMap<Board2, (i32, Move)>,
-    next_transposition_table: HashMap<Board2, (i32, Move)>,
-    being_searched: HashSet<Board2>,
+/// Score of a drawn position.
+pub const DRAW_SCORE: i32 = 0;
+
+pub use board2::WIN_SCORE;
+
+/// Scores at least this far from zero are wins, see `win_score`.
+pub const MIN_WIN_SCORE: i32 = WIN_SCORE - 10000;
+
+/// Score of a game that ends `plies` plies from the root of the search,
+/// with `white_wins` telling the winner. Shorter wins score higher, so the
+/// search prefers them, and longer losses.
+pub fn win_score(white_wins: bool, plies: usize) -> i32 {
+    let score = WIN_SCORE - plies as i32;
+    if white_wins {
+        score
+    } else {
+        -score
+    }
+}
+
+/// The inverse of `win_score`: the number of plies until the game is won,
+/// positive if White wins and negative if Black does, or `None` if `score`
+/// is not a win.
+pub fn win_distance(score: i32) -> Option<i32> {
+    if score >= MIN_WIN_SCORE {
+        Some(WIN_SCORE - score)
+    } else if score <= -MIN_WIN_SCORE {
+        Some(-(WIN_SCORE + score))
+    } else {
+        None
+    }
+}
+
+/// Win scores are stored in the transposition table relative to the stored
+/// position rather than to the root, as the position may be reached at other
+/// plies.
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    match win_distance(score) {
+        Some(plies) => win_score(
+            plies > 0,
+            (plies.unsigned_abs() as usize).saturating_sub(ply),
+        ),
+        None => score,
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    match win_distance(score) {
+        Some(plies) => win_score(plies > 0, plies.unsigned_abs() as usize + ply),
+        None => score,
+    }
+}
+
+/// The score of a cutoff by `entry` at a node searched with `alpha` and
+/// `beta` to `depth` more plies. With a window wider than a null one, the
+/// node may be on the principal variation, so only scores outside the
+/// window are taken: a score inside it would end the variation there.
+fn table_cutoff(entry: tt::Entry, depth: usize, alpha: i32, beta: i32) -> Option<i32> {
+    let null_window = beta as i64 - alpha as i64 <= 1;
+    entry
+        .cutoff(depth, alpha, beta)
+        .filter(|&score| null_window || score <= alpha || score >= beta)
+}
+
+/// Default size of the transposition table in megabytes.
+pub const DEFAULT_TT_SIZE_MB: usize = 32;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SearchOptions {
+    /// Search every root move with a full window to report its exact score.
+    pub collect_first_move_scores: bool,
+    pub repetition_rule: RepetitionRule,
+    /// Memory used by the transposition table, in megabytes.
+    pub tt_size_mb: usize,
+    /// Number of threads searching in parallel, sharing the transposition
+    /// table. Only native builds use more than one.
+    pub threads: usize,
+    pub algorithm: SearchAlgorithm,
+    /// Number of principal variations to report, each starting with a
+    /// different move, in `PartialSearchResult::lines`.
+    pub multi_pv: usize,
+    /// Search a ply deeper after a move that threatens to win, up to
+    /// four plies per line.
+    pub threat_extension: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SearchAlgorithm {
+    /// Minimax with alpha-beta pruning and a full window at every depth.
+    AlphaBeta,
+    /// Negamax principal variation search: moves after the first are only
+    /// proven worse with a zero window, and the root is searched with an
+    /// aspiration window around the score of the previous depth.
+    #[default]
+    Pvs,
+}
+
+impl std::str::FromStr for SearchAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SearchAlgorithm, String> {
+        match s {
+            "alphabeta" => Ok(SearchAlgorithm::AlphaBeta),
+            "pvs" => Ok(SearchAlgorithm::Pvs),
+            _ => Err(format!(
+                "unknown search algorithm `{}`, expected alphabeta or pvs",
+                s
+            )),
+        }
+    }
+}
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions {
+            collect_first_move_scores: false,
+            repetition_rule: RepetitionRule::default(),
+            tt_size_mb: DEFAULT_TT_SIZE_MB,
+            threads: 1,
+            algorithm: SearchAlgorithm::default(),
+            multi_pv: 1,
+            threat_extension: true,
+        }
+    }
+}
+
+/// Bounds on a search. Every limit is optional; without any, the search only
+/// ends when a win is found or the caller's `stop` returns true. The first
+/// depth is always completed, so that a bounded search returns a move
+/// whenever there is one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchLimits {
+    /// Stop after completing this depth.
+    pub max_depth: Option<usize>,
+    /// Abort once this many nodes have been searched, over all depths.
+    pub max_nodes: Option<usize>,
+    /// Do not start a new depth after this many milliseconds.
+    pub soft_time_ms: Option<u64>,
+    /// Abort after this many milliseconds.
+    pub hard_time_ms: Option<u64>,
+}
+
+/// Wall-clock time since the start of a search. `std::time::Instant` is not
+/// available in the browser, where the JS clock is used instead.
+struct Clock {
+    #[cfg(not(target_arch = "wasm32"))]
+    start: std::time::Instant,
+    #[cfg(target_arch = "wasm32")]
+    start: f64,
+}
+
+impl Clock {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn start() -> Clock {
+        Clock {
+            start: std::time::Instant::now(),
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn start() -> Clock {
+        Clock {
+            start: js_sys::Date::now(),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn elapsed_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn elapsed_ms(&self) -> u64 {
+        (js_sys::Date::now() - self.start).max(0.0) as u64
+    }
+}
+
+struct SearchState<'a, E: Evaluator> {
+    transposition_table: &'a TranspositionTable,
+    evaluator: &'a E,
+    /// How often each position occurred in the game and on the current path.
+    repetitions: HashMap<Board2, usize>,
+    repetition_rule: RepetitionRule,
     nodes_searched: usize,
+    /// Nodes at the current depth searched by `quiescence`, which
+    /// `nodes_searched` leaves out.
+    quiescence_nodes: usize,
+    total_nodes_searched: usize,
+    /// Nodes at the current depth where a move caused a cutoff, and where
+    /// that move was the first one searched.
+    cutoffs: usize,
+    first_move_cutoffs: usize,
+    move_ordering: MoveOrdering,
     max_depth: usize,
-    max_transposition_table_depth: usize,
-    stop: Box<dyn Fn() -> bool>,
+    /// Plies the current line is searched beyond `max_depth`, as it
+    /// threatens to win.
+    extension: usize,
+    stop: Box<dyn Fn() -> bool + 'a>,
+    limits: SearchLimits,
+    clock: Clock,
+    /// While pondering, tells whether the expected reply was played; the
+    /// limits only apply from then on.
+    ponder_hit: Option<Box<dyn Fn() -> bool + 'a>>,
+    /// Nodes searched before the limits started to apply.
+    nodes_before_limits: usize,
     collect_first_move_scores: bool,
+    algorithm: SearchAlgorithm,
+    threat_extension: bool,
+    multi_pv: usize,
+    /// Root moves skipped by the search, as they start earlier lines of a
+    /// multi-PV search.
+    excluded_root_moves: Vec<Move>,
 }
 
 struct Interrupted;
 
-impl SearchState {
+/// Bound of the search window, safe to negate.
+const INFINITY: i32 = i32::MAX - 1;
+
+/// Half width of the first aspiration window.
+const ASPIRATION_WINDOW: i32 = 25;
+
+/// Most plies a line is extended by for threats to win.
+const MAX_THREAT_EXTENSION: usize = 4;
+
+/// Most plies `quiescence` searches beyond the horizon, as parrying a threat
+/// may make another.
+const MAX_QUIESCENCE_PLIES: usize = 8;
+
+impl<'a, E: Evaluator> SearchState<'a, E> {
     pub fn new(
-        stop: Box<dyn Fn() -> bool>,
-        collect_first_move_scores: bool,
+        transposition_table: &'a TranspositionTable,
+        evaluator: &'a E,
+        stop: Box<dyn Fn() -> bool + 'a>,
+        options: SearchOptions,
+        limits: SearchLimits,
         history_states: Vec<Board2>,
-    ) -> SearchState {
+    ) -> SearchState<'a, E> {
+        let mut repetitions = HashMap::new();
+        for state in history_states {
+            *repetitions.entry(state).or_insert(0) += 1;
+        }
         SearchState {
-            transposition_table: HashMap::new(),
-            next_transposition_table: HashMap::new(),
-            being_searched: history_states.into_iter().collect(),
+            transposition_table,
+            evaluator,
+            repetitions,
+            repetition_rule: options.repetition_rule,
             nodes_searched: 0,
+            quiescence_nodes: 0,
+            total_nodes_searched: 0,
+            cutoffs: 0,
+            first_move_cutoffs: 0,
+            move_ordering: MoveOrdering::new(),
             max_depth: 0,
-            max_transposition_table_depth: 20,
+            extension: 0,
             stop,
-            collect_first_move_scores,
+            limits,
+            clock: Clock::start(),
+            ponder_hit: None,
+            nodes_before_limits: 0,
+            collect_first_move_scores: options.collect_first_move_scores,
+            algorithm: options.algorithm,
+            threat_extension: options.threat_extension,
+            multi_pv: options.multi_pv.max(1),
+            excluded_root_moves: Vec::new(),
+        }
+    }
+
+    /// Whether the search is still pondering. On the ponder hit, the limits
+    /// start counting from the current time and node count.
+    fn pondering(&mut self) -> bool {
+        match &self.ponder_hit {
+            Some(ponder_hit) if ponder_hit() => {
+                self.ponder_hit = None;
+                self.clock = Clock::start();
+                self.nodes_before_limits = self.total_nodes_searched;
+                false
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Whether the search must be aborted, either by the caller or because
+    /// the node or hard time limit is exhausted.
+    fn should_stop(&mut self) -> bool {
+        if (self.stop)() {
+            return true;
+        }
+        if self.max_depth <= 1 || self.pondering() {
+            return false;
+        }
+        let limits = &self.limits;
+        limits
+            .max_nodes
+            .is_some_and(|n| self.total_nodes_searched - self.nodes_before_limits >= n)
+            // Reading the clock is comparatively slow, especially in wasm.
+            || (self.total_nodes_searched.is_multiple_of(1024)
+                && limits
+                    .hard_time_ms
+                    .is_some_and(|t| self.clock.elapsed_ms() >= t))
+    }
+
+    /// Whether another depth may be started after completing `max_depth`.
+    fn may_deepen(&mut self) -> bool {
+        if self.pondering() {
+            return true;
+        }
+        let limits = &self.limits;
+        limits.max_depth.is_none_or(|d| self.max_depth < d)
+            && limits
+                .soft_time_ms
+                .is_none_or(|t| self.clock.elapsed_ms() < t)
+    }
+
+    /// Static score of `state` from White's point of view, also for a
+    /// position that ended.
+    fn evaluate(&self, state: Board2) -> i32 {
+        if !state.ended() {
+            self.evaluator.evaluate(state)
+        } else if state.white_won() {
+            WIN_SCORE
+        } else {
+            -WIN_SCORE
+        }
+    }
+
+    fn enter(&mut self, state: Board2) {
+        *self.repetitions.entry(state).or_insert(0) += 1;
+    }
+
+    fn leave(&mut self, state: Board2) {
+        if let Some(count) = self.repetitions.get_mut(&state) {
+            *count -= 1;
+            if *count == 0 {
+                self.repetitions.remove(&state);
+            }
         }
     }
 
@@ -73,54 +417,70 @@ impl SearchState {
         mut alpha: i32,
         mut beta: i32,
     ) -> Result<SearchResult, Interrupted> {
-        if (self.stop)() {
+        if self.should_stop() {
             return Err(Interrupted);
         }
-        if depth >= self.max_depth || state.ended() {
-            return Ok(SearchResult {
-                score: state.score(),
-                best_path: vec![],
-                first_move_scores: vec![],
-            });
+        if state.ended() {
+            return Ok(SearchResult::leaf(win_score(state.white_won(), depth)));
+        }
+        if depth >= self.max_depth + self.extension {
+            // The quiescence search is a negamax one.
+            let (alpha, beta) = (alpha.max(-INFINITY), beta.min(INFINITY));
+            let score = if state.maximizing() {
+                self.quiescence(state, depth, 0, alpha, beta)?
+            } else {
+                -self.quiescence(state, depth, 0, -beta, -alpha)?
+            };
+            return Ok(SearchResult::leaf(score));
         }
 
         self.nodes_searched += 1;
+        self.total_nodes_searched += 1;
+
+        let remaining_depth = self.remaining_depth(depth);
+        let tt_entry = self.probe(state, depth);
+        if let Some(entry) = tt_entry {
+            // The root is always searched, to produce a full result.
+            if depth > 0 {
+                if let Some(score) = table_cutoff(entry, remaining_depth, alpha, beta) {
+                    return Ok(SearchResult {
+                        score,
+                        best_path: vec![entry.best_move],
+                        first_move_scores: vec![],
+                    });
+                }
+            }
+        }
+        let (original_alpha, original_beta) = (alpha, beta);
 
         let maximizing = state.maximizing();
         let mut best_path = Vec::new();
         let mut best_score = if maximizing { i32::MIN } else { i32::MAX };
 
-        let mut moves = state.all_moves();
-        moves.retain(|(_, board)| !self.being_searched.contains(&board));
+        let loss = win_score(!maximizing, depth);
+        let moves = self.ordered_moves(state, depth, tt_entry.map(|entry| entry.best_move));
         if moves.is_empty() {
-            return Ok(SearchResult {
-                score: if maximizing { -100000 } else { 100000 },
-                best_path: vec![],
-                first_move_scores: vec![],
-            });
+            return Ok(SearchResult::leaf(loss));
         }
-        let prev_best_move = self.transposition_table.get(&state);
-        moves.sort_by_key(|(m, state)| {
-            if let Some((_, prev_best_move)) = prev_best_move {
-                if m == prev_best_move {
-                    return -10000000;
-                }
-            }
-            if maximizing {
-                -state.score()
-            } else {
-                state.score()
-            }
-        });
 
-        self.being_searched.insert(state);
+        self.enter(state);
         let mut first_move_scores = Vec::new();
-        for (one_move, next_state) in moves.into_iter() {
+        for (i, (one_move, next_state, repetition)) in moves.into_iter().enumerate() {
             let SearchResult {
                 score,
                 best_path: best_subpath,
                 ..
-            } = self.alpha_beta(next_state, depth + 1, alpha, beta)?;
+            } = match repetition {
+                Repetition::Draw => SearchResult::leaf(DRAW_SCORE),
+                Repetition::LossForMover => SearchResult::leaf(win_score(!maximizing, depth + 1)),
+                _ => {
+                    let extension = self.threat_extension(next_state);
+                    self.extension += extension;
+                    let result = self.alpha_beta(next_state, depth + 1, alpha, beta)?;
+                    self.extension -= extension;
+                    result
+                }
+            };
             if maximizing {
                 if score > best_score {
                     best_score = score;
@@ -144,18 +504,156 @@ impl SearchState {
                 first_move_scores.push((one_move, score));
             }
             if alpha >= beta {
+                self.record_cutoff(one_move, depth, i);
                 break;
             }
         }
-        if depth < self.max_transposition_table_depth {
-            if self.next_transposition_table.len() < 30000000 {
-                self.next_transposition_table.insert(
-                    state.clone(),
-                    (best_score, best_path.last().copied().unwrap()),
-                );
+        let bound = if best_score <= original_alpha {
+            Bound::Upper
+        } else if best_score >= original_beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        // With root moves excluded, the root score is not that of the position.
+        if depth > 0 || self.excluded_root_moves.is_empty() {
+            self.transposition_table.insert(
+                state,
+                score_to_tt(best_score, depth),
+                best_path.last().copied().unwrap(),
+                remaining_depth,
+                bound,
+            );
+        }
+        self.leave(state);
+
+        Ok(SearchResult {
+            score: best_score,
+            best_path,
+            first_move_scores,
+        })
+    }
+
+    /// Negamax principal variation search. Unlike `alpha_beta`, the window
+    /// and the returned scores are from the point of view of the side to
+    /// move.
+    fn pvs(
+        &mut self,
+        state: Board2,
+        depth: usize,
+        mut alpha: i32,
+        beta: i32,
+    ) -> Result<SearchResult, Interrupted> {
+        if self.should_stop() {
+            return Err(Interrupted);
+        }
+        let maximizing = state.maximizing();
+        let color = if maximizing { 1 } else { -1 };
+        if state.ended() {
+            return Ok(SearchResult::leaf(
+                win_score(state.white_won(), depth) * color,
+            ));
+        }
+        if depth >= self.max_depth + self.extension {
+            return Ok(SearchResult::leaf(
+                self.quiescence(state, depth, 0, alpha, beta)?,
+            ));
+        }
+
+        self.nodes_searched += 1;
+        self.total_nodes_searched += 1;
+
+        let remaining_depth = self.remaining_depth(depth);
+        let tt_entry =
+            self.probe(state, depth)
+                .map(|entry| if maximizing { entry } else { entry.negated() });
+        if let Some(entry) = tt_entry {
+            // The root is always searched, to produce a full result.
+            if depth > 0 {
+                if let Some(score) = table_cutoff(entry, remaining_depth, alpha, beta) {
+                    return Ok(SearchResult {
+                        score,
+                        best_path: vec![entry.best_move],
+                        first_move_scores: vec![],
+                    });
+                }
             }
         }
-        self.being_searched.remove(&state);
+        let original_alpha = alpha;
+
+        let moves = self.ordered_moves(state, depth, tt_entry.map(|entry| entry.best_move));
+        if moves.is_empty() {
+            return Ok(SearchResult::leaf(-win_score(true, depth)));
+        }
+
+        // Every root move needs an exact score when collecting them.
+        let full_window = depth == 0 && self.collect_first_move_scores;
+        let mut best_path = Vec::new();
+        let mut best_score = -INFINITY;
+        self.enter(state);
+        let mut first_move_scores = Vec::new();
+        for (i, (one_move, next_state, repetition)) in moves.into_iter().enumerate() {
+            let extension = match repetition {
+                Repetition::Draw | Repetition::LossForMover => 0,
+                _ => self.threat_extension(next_state),
+            };
+            self.extension += extension;
+            let (score, best_subpath) = match repetition {
+                Repetition::Draw => (DRAW_SCORE, vec![]),
+                Repetition::LossForMover => (-win_score(true, depth + 1), vec![]),
+                _ if i == 0 || full_window => {
+                    let result = self.pvs(next_state, depth + 1, -beta, -alpha)?;
+                    (-result.score, result.best_path)
+                }
+                _ => {
+                    let mut result = self.pvs(next_state, depth + 1, -alpha - 1, -alpha)?;
+                    if -result.score > alpha && -result.score < beta {
+                        result = self.pvs(next_state, depth + 1, -beta, -alpha)?;
+                    }
+                    (-result.score, result.best_path)
+                }
+            };
+            self.extension -= extension;
+            if score > best_score {
+                best_score = score;
+                best_path = best_subpath;
+                best_path.push(one_move);
+            }
+            if depth == 0 && self.collect_first_move_scores {
+                first_move_scores.push((one_move, score * color));
+            }
+            if score > alpha && !full_window {
+                alpha = score;
+            }
+            if alpha >= beta {
+                self.record_cutoff(one_move, depth, i);
+                break;
+            }
+        }
+        let bound = if best_score <= original_alpha {
+            Bound::Upper
+        } else if best_score >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        // The table holds scores from White's point of view.
+        let (white_score, bound) = if maximizing {
+            (best_score, bound)
+        } else {
+            (-best_score, bound.negated())
+        };
+        // With root moves excluded, the root score is not that of the position.
+        if depth > 0 || self.excluded_root_moves.is_empty() {
+            self.transposition_table.insert(
+                state,
+                score_to_tt(white_score, depth),
+                best_path.last().copied().unwrap(),
+                remaining_depth,
+                bound,
+            );
+        }
+        self.leave(state);
 
         Ok(SearchResult {
             score: best_score,
@@ -164,180 +662,1091 @@ impl SearchState {
         })
     }
 
-    fn next_depth(&mut self) {
-        std::mem::swap(
-            &mut self.transposition_table,
-            &mut self.next_transposition_table,
-        );
-        self.next_transposition_table.clear();
+    /// Scores `state`, a leaf `depth` plies from the root and `plies` beyond
+    /// the horizon, from the point of view of the side to move as `pvs`
+    /// does. Rather than evaluating a position where a king can reach the
+    /// centre on the next move, it takes the win, or searches the moves
+    /// that parry the threat.
+    fn quiescence(
+        &mut self,
+        state: Board2,
+        depth: usize,
+        plies: usize,
+        mut alpha: i32,
+        beta: i32,
+    ) -> Result<i32, Interrupted> {
+        if self.should_stop() {
+            return Err(Interrupted);
+        }
+        let (player, opponent) = if state.maximizing() {
+            (Player::White, Player::Black)
+        } else {
+            (Player::Black, Player::White)
+        };
+        if state.threats(player) != 0 {
+            return Ok(win_score(true, depth + 1));
+        }
+        let color = if state.maximizing() { 1 } else { -1 };
+        if state.threats(opponent) == 0 || plies >= MAX_QUIESCENCE_PLIES {
+            return Ok(self.evaluator.evaluate(state) * color);
+        }
+
+        self.quiescence_nodes += 1;
+        self.total_nodes_searched += 1;
+
+        let moves = self.ordered_moves(state, depth, None);
+        if moves.is_empty() {
+            return Ok(-win_score(true, depth));
+        }
+        // Unless a move parries the threat, the opponent wins next move.
+        let mut best_score = -win_score(true, depth + 2);
+        self.enter(state);
+        for (_, next_state, repetition) in moves {
+            if next_state.threats(opponent) != 0 {
+                continue;
+            }
+            let score = match repetition {
+                Repetition::Draw => DRAW_SCORE,
+                Repetition::LossForMover => -win_score(true, depth + 1),
+                _ => -self.quiescence(next_state, depth + 1, plies + 1, -beta, -alpha)?,
+            };
+            best_score = best_score.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        self.leave(state);
+        Ok(best_score)
+    }
+
+    /// Plies left to search below a node `depth` plies from the root.
+    fn remaining_depth(&self, depth: usize) -> usize {
+        self.max_depth + self.extension - depth
+    }
+
+    /// Plies to extend the search below `next_state` by: one if the move to
+    /// it threatens to win next move, so that the answers to the threat are
+    /// searched as deep as other moves.
+    fn threat_extension(&self, next_state: Board2) -> usize {
+        usize::from(
+            self.threat_extension
+                && self.extension < MAX_THREAT_EXTENSION
+                && next_state.is_threatened(),
+        )
+    }
+
+    /// The transposition table entry for `state` at `depth` plies from the
+    /// root, with win scores relative to the root.
+    fn probe(&self, state: Board2, depth: usize) -> Option<tt::Entry> {
+        self.transposition_table.get(state).map(|mut entry| {
+            entry.score = score_from_tt(entry.score, depth);
+            entry
+        })
+    }
 
+    /// The moves of the side to move that the repetition rule allows, with
+    /// their resulting boards, in the order to search them: `best_move` from
+    /// the transposition table first, then the killer moves of the ply, then
+    /// by the static score of the child, ties broken by history score.
+    fn ordered_moves(
+        &self,
+        state: Board2,
+        depth: usize,
+        best_move: Option<Move>,
+    ) -> Vec<(Move, Board2, Repetition)> {
+        let rule = self.repetition_rule;
+        let maximizing = state.maximizing();
+        let mut moves = state
+            .all_moves()
+            .into_iter()
+            .map(|(m, board)| {
+                let occurrences = self.repetitions.get(&board).copied().unwrap_or(0);
+                (m, board, rule.judge(occurrences))
+            })
+            .filter(|(_, _, repetition)| *repetition != Repetition::Illegal)
+            .filter(|(m, _, _)| depth > 0 || !self.excluded_root_moves.contains(m))
+            .collect::<Vec<_>>();
+        let ordering = &self.move_ordering;
+        moves.sort_by_cached_key(|(m, state, _)| {
+            let rank = if best_move == Some(*m) {
+                0
+            } else {
+                ordering.killer_rank(*m, depth).map_or(3, |k| k + 1)
+            };
+            let score = if maximizing {
+                -self.evaluate(*state)
+            } else {
+                self.evaluate(*state)
+            };
+            (rank, score, std::cmp::Reverse(ordering.history(*m)))
+        });
+        moves
+    }
+
+    /// Records a cutoff by `m`, the `index`-th move searched at `depth`.
+    fn record_cutoff(&mut self, m: Move, depth: usize, index: usize) {
+        self.cutoffs += 1;
+        if index == 0 {
+            self.first_move_cutoffs += 1;
+        }
+        self.move_ordering
+            .record_cutoff(m, depth, self.remaining_depth(depth));
+    }
+
+    /// Searches the root at the current depth, returning scores from White's
+    /// point of view. `previous_score` is the score of the previous depth,
+    /// around which PVS opens an aspiration window.
+    fn search_root(
+        &mut self,
+        state: Board2,
+        previous_score: Option<i32>,
+    ) -> Result<SearchResult, Interrupted> {
+        if self.algorithm == SearchAlgorithm::AlphaBeta {
+            return self.alpha_beta(state, 0, i32::MIN, i32::MAX);
+        }
+        let color = if state.maximizing() { 1 } else { -1 };
+        let (mut alpha, mut beta) = match previous_score {
+            Some(score) if win_distance(score).is_none() && !self.collect_first_move_scores => {
+                let score = score * color;
+                (score - ASPIRATION_WINDOW, score + ASPIRATION_WINDOW)
+            }
+            _ => (-INFINITY, INFINITY),
+        };
+        let mut delta = ASPIRATION_WINDOW;
+        loop {
+            let mut result = self.pvs(state, 0, alpha, beta)?;
+            // Widen the side of the window that the score fell outside of.
+            if result.score <= alpha && alpha > -INFINITY {
+                delta = delta.saturating_mul(4);
+                alpha = result.score.saturating_sub(delta).max(-INFINITY);
+            } else if result.score >= beta && beta < INFINITY {
+                delta = delta.saturating_mul(4);
+                beta = result.score.saturating_add(delta).min(INFINITY);
+            } else {
+                result.score *= color;
+                return Ok(result);
+            }
+        }
+    }
+
+    fn next_depth(&mut self) {
         self.max_depth += 1;
+        self.extension = 0;
         self.nodes_searched = 0;
+        self.quiescence_nodes = 0;
+        self.cutoffs = 0;
+        self.first_move_cutoffs = 0;
+        self.move_ordering.age();
     }
 }
 
+/// One principal variation of a multi-PV search.
+#[derive(Clone, Debug, Serialize)]
+pub struct SearchLine {
+    pub depth: usize,
+    pub score: i32,
+    pub mate: Option<i32>,
+    /// The moves of the line, last move first like `SearchResult::best_path`.
+    pub best_path: Vec<Move>,
+}
+
 #[derive(Serialize)]
 pub struct PartialSearchResult {
     pub depth: usize,
+    /// Plies until the game is won with best play, positive if White wins,
+    /// if the search found a forced win.
+    pub mate: Option<i32>,
     pub nodes_searched: usize,
+    /// Nodes beyond the horizon searched for threats to win, not counted in
+    /// `nodes_searched`.
+    pub quiescence_nodes: usize,
+    /// Nodes that were cut off, and how many of them by their first move; a
+    /// high ratio means good move ordering.
+    pub cutoffs: usize,
+    pub first_move_cutoffs: usize,
     pub transposition_table_size: usize,
     pub result: SearchResult,
+    /// The best `SearchOptions::multi_pv` lines, best first; the first one
+    /// is the line of `result`.
+    pub lines: Vec<SearchLine>,
+}
+
+/// A search context for a whole game. The transposition table and the
+/// history of moves causing cutoffs carry over from one search to the next,
+/// so that each move benefits from what the searches of earlier moves
+/// learned. Positions are evaluated by `E`.
+pub struct Searcher<E: Evaluator = TableEvaluator> {
+    options: SearchOptions,
+    evaluator: E,
+    transposition_table: Arc<TranspositionTable>,
+    move_ordering: MoveOrdering,
+}
+
+impl Searcher {
+    /// A searcher with the default evaluator.
+    pub fn new(options: SearchOptions) -> Searcher {
+        Searcher::with_evaluator(options, TableEvaluator::default())
+    }
+}
+
+impl<E: Evaluator> Searcher<E> {
+    pub fn with_evaluator(options: SearchOptions, evaluator: E) -> Searcher<E> {
+        Searcher {
+            options,
+            evaluator,
+            transposition_table: Arc::new(TranspositionTable::new(options.tt_size_mb)),
+            move_ordering: MoveOrdering::new(),
+        }
+    }
+
+    pub fn evaluator(&self) -> &E {
+        &self.evaluator
+    }
+
+    /// Replaces the evaluator. Entries of the transposition table scored by
+    /// the old one are forgotten.
+    pub fn set_evaluator(&mut self, evaluator: E) {
+        self.evaluator = evaluator;
+        self.transposition_table.clear();
+    }
+
+    pub fn options(&self) -> SearchOptions {
+        self.options
+    }
+
+    /// Changes the options of later searches. The transposition table is
+    /// only reallocated, losing its entries, if its size changes, and
+    /// cleared if the repetition rule changes, as its scores depend on it.
+    pub fn set_options(&mut self, options: SearchOptions) {
+        if options.tt_size_mb != self.options.tt_size_mb {
+            self.transposition_table = Arc::new(TranspositionTable::new(options.tt_size_mb));
+        } else if options.repetition_rule != self.options.repetition_rule {
+            self.transposition_table.clear();
+        }
+        self.options = options;
+    }
+
+    /// Forgets everything learned by earlier searches, as they are of little
+    /// use in another game.
+    pub fn new_game(&mut self) {
+        self.transposition_table.clear();
+        self.move_ordering = MoveOrdering::new();
+    }
+
+    /// Number of positions stored in the transposition table.
+    pub fn transposition_table_size(&self) -> usize {
+        self.transposition_table.len()
+    }
+
+    /// Searches `state` with iterative deepening until `stop` returns true, a
+    /// win is found or one of the `limits` is reached, reporting every
+    /// completed depth to `partial`.
+    /// `history_states` are the positions played so far in the game, for the
+    /// repetition rule; it may or may not end with `state` itself.
+    pub fn find_best_move(
+        &mut self,
+        state: Board2,
+        stop: impl Fn() -> bool,
+        partial: impl Fn(PartialSearchResult),
+        limits: SearchLimits,
+        history_states: Vec<Board2>,
+    ) -> Option<Move> {
+        self.search(state, stop, None, partial, limits, history_states)
+    }
+
+    /// Like `find_best_move`, but searches on the opponent's time: `state`
+    /// is the position after the reply expected by the previous search (see
+    /// `SearchResult::expected_reply`). The `limits` are ignored until
+    /// `ponder_hit` returns true, which the caller signals once that reply is
+    /// actually played; the same search then continues, keeping its
+    /// completed depths, with the limits counted from the ponder hit. A win
+    /// found while pondering still ends the search early.
+    pub fn ponder(
+        &mut self,
+        state: Board2,
+        stop: impl Fn() -> bool,
+        ponder_hit: impl Fn() -> bool,
+        partial: impl Fn(PartialSearchResult),
+        limits: SearchLimits,
+        history_states: Vec<Board2>,
+    ) -> Option<Move> {
+        self.search(
+            state,
+            stop,
+            Some(Box::new(ponder_hit)),
+            partial,
+            limits,
+            history_states,
+        )
+    }
+
+    fn search<'a>(
+        &mut self,
+        state: Board2,
+        stop: impl Fn() -> bool + 'a,
+        ponder_hit: Option<Box<dyn Fn() -> bool + 'a>>,
+        partial: impl Fn(PartialSearchResult),
+        limits: SearchLimits,
+        mut history_states: Vec<Board2>,
+    ) -> Option<Move> {
+        if history_states.last() == Some(&state) {
+            history_states.pop();
+        }
+        let options = self.options;
+        let evaluator = &self.evaluator;
+        let table = self.transposition_table.clone();
+        let transposition_table = &*table;
+        transposition_table.new_search();
+        let mut move_ordering = std::mem::replace(&mut self.move_ordering, MoveOrdering::new());
+        move_ordering.new_search();
+        let search = |history_states| {
+            let mut search_state = SearchState::new(
+                transposition_table,
+                evaluator,
+                Box::new(stop),
+                options,
+                limits,
+                history_states,
+            );
+            search_state.move_ordering = move_ordering;
+            search_state.ponder_hit = ponder_hit;
+            let best_move = iterative_deepening(&mut search_state, state, partial);
+            (best_move, search_state.move_ordering)
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if options.threads > 1 {
+            // Lazy SMP: helper threads search the same position independently,
+            // speeding up the main search through the shared transposition
+            // table.
+            let helpers_stop = AtomicBool::new(false);
+            let (best_move, move_ordering) = std::thread::scope(|scope| {
+                for id in 1..options.threads {
+                    let history_states = history_states.clone();
+                    let helpers_stop = &helpers_stop;
+                    std::thread::Builder::new()
+                        .stack_size(HELPER_STACK_SIZE)
+                        .spawn_scoped(scope, move || {
+                            run_helper(
+                                transposition_table,
+                                evaluator,
+                                state,
+                                || helpers_stop.load(Ordering::Relaxed),
+                                options,
+                                history_states,
+                                id,
+                            );
+                        })
+                        .unwrap();
+                }
+                let result = search(history_states);
+                helpers_stop.store(true, Ordering::Relaxed);
+                result
+            });
+            self.move_ordering = move_ordering;
+            return best_move;
+        }
+        let (best_move, move_ordering) = search(history_states);
+        self.move_ordering = move_ordering;
+        best_move
+    }
 }
 
+/// Searches `state` with a new `Searcher`, see `Searcher::find_best_move`.
 pub fn find_best_move(
     state: Board2,
     stop: impl Fn() -> bool + 'static,
     partial: impl Fn(PartialSearchResult),
-    collect_first_move_scores: bool,
+    options: SearchOptions,
+    limits: SearchLimits,
+    history_states: Vec<Board2>,
+) -> Option<Move> {
+    Searcher::new(options).find_best_move(state, stop, partial, limits, history_states)
+}
+
+/// Ponders on `state` with a new `Searcher`, see `Searcher::ponder`.
+pub fn ponder(
+    state: Board2,
+    stop: impl Fn() -> bool + 'static,
+    ponder_hit: impl Fn() -> bool + 'static,
+    partial: impl Fn(PartialSearchResult),
+    options: SearchOptions,
+    limits: SearchLimits,
     history_states: Vec<Board2>,
 ) -> Option<Move> {
-    let mut search_state =
-        SearchState::new(Box::new(stop), collect_first_move_scores, history_states);
+    Searcher::new(options).ponder(state, stop, ponder_hit, partial, limits, history_states)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const HELPER_STACK_SIZE: usize = 64 * 1024 * 1024;
+
+fn iterative_deepening<E: Evaluator>(
+    search_state: &mut SearchState<E>,
+    state: Board2,
+    partial: impl Fn(PartialSearchResult),
+) -> Option<Move> {
     let mut result: Option<SearchResult> = None;
-    loop {
+    let mut lines: Vec<SearchLine> = Vec::new();
+    'deepening: loop {
         search_state.next_depth();
         let depth = search_state.max_depth;
-        match search_state.alpha_beta(state, 0, i32::MIN, i32::MAX) {
-            Err(Interrupted) => {
-                break;
+        // Each line is the best one among the moves not starting an earlier
+        // line.
+        let mut best = None;
+        let mut new_lines = Vec::new();
+        for k in 0..search_state.multi_pv {
+            let previous_score = lines.get(k).map(|line| line.score);
+            let one_result = match search_state.search_root(state, previous_score) {
+                Ok(one_result) => one_result,
+                Err(Interrupted) => break 'deepening,
+            };
+            match one_result.best_path.last() {
+                Some(&m) => search_state.excluded_root_moves.push(m),
+                // Every move already starts a line.
+                None if k > 0 => break,
+                None => {}
             }
-            Ok(one_result) => {
-                partial(PartialSearchResult {
-                    depth,
-                    nodes_searched: search_state.nodes_searched,
-                    transposition_table_size: search_state.next_transposition_table.len(),
-                    result: one_result.clone(),
-                });
-                let win_found = one_result.score.abs() > 10000;
-                result = Some(one_result);
-                if win_found {
-                    break;
-                }
+            new_lines.push(SearchLine {
+                depth,
+                score: one_result.score,
+                mate: win_distance(one_result.score),
+                best_path: one_result.best_path.clone(),
+            });
+            if k == 0 {
+                best = Some(one_result);
             }
         }
+        search_state.excluded_root_moves.clear();
+        let one_result = best.unwrap();
+        let mate = win_distance(one_result.score);
+        partial(PartialSearchResult {
+            depth,
+            mate,
+            nodes_searched: search_state.nodes_searched,
+            quiescence_nodes: search_state.quiescence_nodes,
+            cutoffs: search_state.cutoffs,
+            first_move_cutoffs: search_state.first_move_cutoffs,
+            transposition_table_size: search_state.transposition_table.len(),
+            result: one_result.clone(),
+            lines: new_lines.clone(),
+        });
+        // A win within the searched depth is the shortest there is; a longer
+        // one, known from the transposition table, may not be.
+        let shortest_win_found = mate.is_some_and(|plies| plies.unsigned_abs() as usize <= depth);
+        result = Some(one_result);
+        lines = new_lines;
+        if shortest_win_found || !search_state.may_deepen() {
+            break;
+        }
     }
     result?.best_path.last().copied()
 }
 
+/// Iterative deepening for helper number `id` of a parallel search, until
+/// `stop` returns true. Odd helpers skip every other depth, so that the
+/// threads spread over more depths.
+#[cfg(any(not(target_arch = "wasm32"), feature = "wasm-threads"))]
+fn run_helper<E: Evaluator>(
+    transposition_table: &TranspositionTable,
+    evaluator: &E,
+    state: Board2,
+    stop: impl Fn() -> bool,
+    options: SearchOptions,
+    history_states: Vec<Board2>,
+    id: usize,
+) {
+    let mut search_state = SearchState::new(
+        transposition_table,
+        evaluator,
+        Box::new(stop),
+        options,
+        SearchLimits::default(),
+        history_states,
+    );
+    let mut previous_score = None;
+    loop {
+        search_state.next_depth();
+        if id % 2 == 1 && search_state.max_depth % 2 == 1 {
+            continue;
+        }
+        match search_state.search_root(state, previous_score) {
+            Ok(result) => previous_score = Some(result.score),
+            Err(Interrupted) => break,
+        }
+    }
+}
+
+/// Decodes a board and a list of boards given as 11 values each, as taken by
+/// `Board2::try_from_positions`.
+fn parse_boards(state: &[u8], history_states: Vec<u8>) -> Result<(Board2, Vec<Board2>), JsValue> {
+    let to_js_error = |e: PositionError| JsValue::from_str(&e.to_string());
+    let state = Board2::try_from_positions(state).map_err(to_js_error)?;
+    let history_states = history_states
+        .into_iter()
+        .chunks(11)
+        .into_iter()
+        .map(|s| Board2::try_from_positions(&s.collect::<Vec<_>>()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(to_js_error)?;
+    Ok((state, history_states))
+}
+
+/// The evaluator for optional weights in JSON, or the default one.
+fn parse_weights(weights: Option<String>) -> Result<TableEvaluator, JsValue> {
+    match weights {
+        Some(weights) => {
+            TableEvaluator::from_json(&weights).map_err(|e| JsValue::from_str(&e.to_string()))
+        }
+        None => Ok(TableEvaluator::default()),
+    }
+}
+
+/// The engine as used by the web app. It keeps one `Searcher` for the whole
+/// game, until `new_game` is called.
 #[wasm_bindgen]
 pub struct Engine {
-    stop: Rc<js_sys::Uint8Array>,
     partial: js_sys::Function,
+    searcher: Searcher,
+    /// One slot per helper worker, see `wasm_threads`.
+    #[cfg(all(target_arch = "wasm32", feature = "wasm-threads"))]
+    helper_slots: Vec<Arc<wasm_threads::HelperSlot>>,
 }
 
 #[wasm_bindgen]
 impl Engine {
+    /// Creates an engine reporting partial results to `partial`. `weights`
+    /// optionally replaces the default evaluation weights, in the JSON form
+    /// described in `eval`.
     #[wasm_bindgen(constructor)]
-    pub fn new(stop: js_sys::Uint8Array, partial: js_sys::Function) -> Engine {
-        Engine {
-            stop: Rc::new(stop),
+    pub fn new(partial: js_sys::Function, weights: Option<String>) -> Result<Engine, JsValue> {
+        Ok(Engine {
             partial,
+            searcher: Searcher::with_evaluator(SearchOptions::default(), parse_weights(weights)?),
+            #[cfg(all(target_arch = "wasm32", feature = "wasm-threads"))]
+            helper_slots: Vec::new(),
+        })
+    }
+
+    /// Sets the size of the transposition table used by later searches, in
+    /// megabytes. Throws if the size is 0 or too large to address.
+    pub fn set_hash_size(&mut self, size_mb: usize) -> Result<(), JsValue> {
+        if !(1..=TranspositionTable::MAX_SIZE_MB).contains(&size_mb) {
+            return Err(JsValue::from_str(&format!("bad hash size {} MB", size_mb)));
         }
+        let options = self.searcher.options();
+        self.searcher.set_options(SearchOptions {
+            tt_size_mb: size_mb,
+            ..options
+        });
+        Ok(())
     }
 
+    /// Sets the number of principal variations reported in the `lines` of
+    /// each partial result.
+    pub fn set_multi_pv(&mut self, lines: usize) {
+        let options = self.searcher.options();
+        self.searcher.set_options(SearchOptions {
+            multi_pv: lines.max(1),
+            ..options
+        });
+    }
+
+    /// Sets the repetition rule by name: `forbidden` (the default),
+    /// `draw_on_threefold` or `loss_for_repeater`.
+    pub fn set_repetition_rule(&mut self, rule: &str) -> Result<(), JsValue> {
+        let options = self.searcher.options();
+        self.searcher.set_options(SearchOptions {
+            repetition_rule: rule.parse().map_err(|e: String| JsValue::from_str(&e))?,
+            ..options
+        });
+        Ok(())
+    }
+
+    /// Forgets what the searches of the previous game learned.
+    pub fn new_game(&mut self) {
+        self.searcher.new_game();
+    }
+
+    /// Searches `state` until `stop[0]` is set or until the `limits` are
+    /// reached, given as the JSON form of `SearchLimits` (e.g.
+    /// `{"max_depth": 8}`; `{}` for no limits). Throws a descriptive error
+    /// instead of searching if `state` or one of `history_states` is not a
+    /// legal board.
     pub fn find_best_move(
-        &self,
+        &mut self,
+        stop: js_sys::Uint8Array,
         state: Vec<u8>,
         collect_first_move_scores: bool,
         history_states: Vec<u8>,
-    ) -> Option<String> {
-        let state = Board2::from_positions(&state);
-        let stop = self.stop.clone();
-        let m = find_best_move(
+        limits: &str,
+    ) -> Result<Option<String>, JsValue> {
+        self.search(
+            stop,
             state,
-            move || js_sys::Atomics::load(&stop, 0).unwrap() != 0,
-            |result| {
-                let m = serde_json::to_string(&result).unwrap();
-                let m = JsValue::from_str(&m);
-                self.partial.call1(&JsValue::NULL, &m).unwrap();
-            },
             collect_first_move_scores,
-            history_states
-                .into_iter()
-                .chunks(11)
-                .into_iter()
-                .map(|s| Board2::from_positions(&s.collect()))
-                .collect(),
-        );
-        m.map(|m| serde_json::to_string(&m).unwrap())
+            history_states,
+            limits,
+            false,
+        )
+    }
+
+    /// Like `find_best_move`, but ponders on `state`, the position after the
+    /// opponent's expected reply: the limits only apply once `stop[1]` is
+    /// set, on the ponder hit. See `Searcher::ponder`.
+    pub fn ponder(
+        &mut self,
+        stop: js_sys::Uint8Array,
+        state: Vec<u8>,
+        collect_first_move_scores: bool,
+        history_states: Vec<u8>,
+        limits: &str,
+    ) -> Result<Option<String>, JsValue> {
+        self.search(
+            stop,
+            state,
+            collect_first_move_scores,
+            history_states,
+            limits,
+            true,
+        )
     }
 }
 
+impl Engine {
+    fn search(
+        &mut self,
+        stop: js_sys::Uint8Array,
+        state: Vec<u8>,
+        collect_first_move_scores: bool,
+        history_states: Vec<u8>,
+        limits: &str,
+        pondering: bool,
+    ) -> Result<Option<String>, JsValue> {
+        let limits: SearchLimits = serde_json::from_str(limits)
+            .map_err(|e| JsValue::from_str(&format!("invalid search limits: {}", e)))?;
+        let (state, history_states) = parse_boards(&state, history_states)?;
+        let stop = Rc::new(stop);
+        let ponder_hit = {
+            let stop = stop.clone();
+            move || js_sys::Atomics::load(&stop, 1).unwrap() != 0
+        };
+        let stop = move || js_sys::Atomics::load(&stop, 0).unwrap() != 0;
+        let callback = &self.partial;
+        let partial = |result| {
+            let m = serde_json::to_string(&result).unwrap();
+            let m = JsValue::from_str(&m);
+            callback.call1(&JsValue::NULL, &m).unwrap();
+        };
+        let options = self.searcher.options();
+        self.searcher.set_options(SearchOptions {
+            collect_first_move_scores,
+            ..options
+        });
+        let m = if pondering {
+            self.searcher
+                .ponder(state, stop, ponder_hit, partial, limits, history_states)
+        } else {
+            self.searcher
+                .find_best_move(state, stop, partial, limits, history_states)
+        };
+        Ok(m.map(|m| serde_json::to_string(&m).unwrap()))
+    }
+}
+
+#[test]
+fn test_search_avoids_losing_repetition() {
+    let mut game =
+        game::Game::with_repetition_rule(Board2::new_original(), RepetitionRule::LossForRepeater);
+    for m in ["a1-d4", "a5-d2", "d4-a1"] {
+        game.play_notation(m).unwrap();
+    }
+    let repeating_move = game.current().parse_move("d2-a5").unwrap();
+    let best_move = find_best_move(
+        game.current(),
+        || false,
+        |_| {},
+        SearchOptions {
+            repetition_rule: game.repetition_rule(),
+            ..SearchOptions::default()
+        },
+        SearchLimits {
+            max_nodes: Some(5000),
+            ..SearchLimits::default()
+        },
+        game.positions().to_vec(),
+    );
+    assert_ne!(best_move, Some(repeating_move));
+}
+
 #[wasm_bindgen_test]
 fn test_basic_engine() {
-    let engine = Engine::new(
-        js_sys::Uint8Array::new_with_length(1),
-        js_sys::Function::default(),
+    let mut engine = Engine::new(js_sys::Function::default(), None).unwrap();
+    let m = engine
+        .find_best_move(
+            js_sys::Uint8Array::new_with_length(1),
+            vec![0, 1, 3, 4, 20, 21, 23, 24, 22, 2, 1],
+            false,
+            vec![],
+            r#"{"max_depth": 4}"#,
+        )
+        .unwrap();
+    assert!(m.is_some());
+}
+
+#[test]
+fn test_search_limits() {
+    use std::cell::Cell;
+
+    let depths = Cell::new(0);
+    let nodes = Cell::new(0);
+    let best_move = find_best_move(
+        Board2::new_original(),
+        || false,
+        |partial| {
+            depths.set(partial.depth);
+            nodes.set(nodes.get() + partial.nodes_searched);
+        },
+        SearchOptions::default(),
+        SearchLimits {
+            max_depth: Some(4),
+            ..SearchLimits::default()
+        },
+        vec![],
     );
-    // does not terminate.
-    println!(
-        "{:?}",
-        engine.find_best_move(vec![0, 1, 3, 4, 20, 21, 23, 24, 22, 2, 1], false, vec![])
+    assert!(best_move.is_some());
+    assert_eq!(depths.get(), 4);
+
+    // A node budget smaller than depth 4 needs stops the search earlier,
+    // but the first depth always completes.
+    for max_nodes in [0, nodes.get() - 1] {
+        depths.set(0);
+        let best_move = find_best_move(
+            Board2::new_original(),
+            || false,
+            |partial| depths.set(partial.depth),
+            SearchOptions::default(),
+            SearchLimits {
+                max_nodes: Some(max_nodes),
+                ..SearchLimits::default()
+            },
+            vec![],
+        );
+        assert!(best_move.is_some());
+        assert!((1..4).contains(&depths.get()));
+    }
+}
+
+#[test]
+fn test_parallel_search() {
+    let board = Board2::new_with_king_inversed();
+    let best_move = find_best_move(
+        board,
+        || false,
+        |_| {},
+        SearchOptions {
+            threads: 4,
+            ..SearchOptions::default()
+        },
+        SearchLimits {
+            max_depth: Some(6),
+            ..SearchLimits::default()
+        },
+        vec![],
     );
+    assert!(board
+        .legal_moves()
+        .iter()
+        .any(|(m, _)| Some(*m) == best_move));
 }
 
-// fn analyze_board(board: Board2) -> i32 {
-//     let mut search_state = SearchState::new();
-//     let start_time = Instant::now();
-//     let mut result = loop {
-//         search_state.next_depth();
-//         let result = search_state.alpha_beta(board, 0, i32::MIN, i32::MAX);
-//         if start_time.elapsed().as_secs() > 20 || result.score.abs() > 10000 {
-//             break result;
-//         }
-//     };
-//     result.best_path.reverse();
-
-//     println!(
-//         "\nscore: {} depth: {}\n{}",
-//         result.score,
-//         search_state.max_depth,
-//         result.best_path.vec_debug()
-//     );
-//     result.score
-// }
-
-// fn run() {
-//     let mut board = Board2::new_with_king_inversed();
-//     // board = board.do_move(Move::new(40, 7));
-//     // board = board.do_move(Move::new(20, 8));
-//     // // board = board.do_move(Move::new(5, 5));
-//     // board = board.do_move(Move::new(5, 16));
-//     loop {
-//         println!("=======================================");
-//         println!("{:?}", board);
-//         println!("=======================================");
-//         let mut all_moves = board
-//             .all_moves()
-//             .into_iter()
-//             .map(|m| (m, analyze_board(m)))
-//             .filter(|(m, s)| {
-//                 if board.maximizing() {
-//                     *s > -10000
-//                 } else {
-//                     *s < 10000
-//                 }
-//             })
-//             .collect::<Vec<_>>();
-//         // let m = rand::thread_rng().gen_range(0..all_moves.len());
-//         all_moves.sort_by_key(|(_, s)| if board.maximizing() { -s } else { *s });
-//         board = all_moves[0].0;
-//     }
-// }
-
-use std::thread;
-
-// use crate::board::VecDebug;
-
-const STACK_SIZE: usize = 400 * 1024 * 1024;
-
-// fn main() {
-//     // Spawn thread with explicit stack size
-//     let child = thread::Builder::new()
-//         .stack_size(STACK_SIZE)
-//         .spawn(run)
-//         .unwrap();
-
-//     // Wait for thread to join
-//     child.join().unwrap();
-// }
+#[test]
+fn test_win_scores() {
+    assert_eq!(win_distance(win_score(true, 7)), Some(7));
+    assert_eq!(win_distance(win_score(false, 4)), Some(-4));
+    assert_eq!(win_distance(250), None);
+    assert!(win_score(true, 3) > win_score(true, 9));
+    assert!(win_score(false, 3) < win_score(false, 9));
+    let score = win_score(false, 9);
+    assert_eq!(score_from_tt(score_to_tt(score, 4), 4), score);
+    assert_eq!(score_from_tt(score_to_tt(score, 4), 2), win_score(false, 7));
+
+    // The shortest win from the start is found at depth 7, ending the search.
+    let last = std::cell::Cell::new((0, None));
+    find_best_move(
+        Board2::new_original(),
+        || false,
+        |partial| last.set((partial.depth, partial.mate)),
+        SearchOptions::default(),
+        SearchLimits {
+            max_depth: Some(12),
+            ..SearchLimits::default()
+        },
+        vec![],
+    );
+    assert_eq!(last.get(), (7, Some(7)));
+}
+
+#[test]
+fn test_threat_extension() {
+    // e2-c4 lets White's king slide onto the centre against it and Black
+    // cannot stop it. The threat extends the line, so that searching two
+    // plies finds the win on the third.
+    for algorithm in [SearchAlgorithm::AlphaBeta, SearchAlgorithm::Pvs] {
+        let last = std::cell::Cell::new(None);
+        let best_move = find_best_move(
+            Board2::parse_notation("pPK2/3pP/4k/5/pp1PP w").unwrap(),
+            || false,
+            |partial| last.set(partial.mate),
+            SearchOptions {
+                algorithm,
+                ..SearchOptions::default()
+            },
+            SearchLimits {
+                max_depth: Some(2),
+                ..SearchLimits::default()
+            },
+            vec![],
+        );
+        assert_eq!(best_move.unwrap().to_notation(), "e2-c4");
+        assert_eq!(last.get(), Some(3));
+    }
+}
+
+#[test]
+fn test_quiescence() {
+    // Searching a single ply, the quiescence search finds that Black cannot
+    // parry the threat of e2-c4, without extending the search.
+    for algorithm in [SearchAlgorithm::AlphaBeta, SearchAlgorithm::Pvs] {
+        let last = std::cell::Cell::new((None, 0));
+        let best_move = find_best_move(
+            Board2::parse_notation("pPK2/3pP/4k/5/pp1PP w").unwrap(),
+            || false,
+            |partial| last.set((partial.mate, partial.quiescence_nodes)),
+            SearchOptions {
+                algorithm,
+                threat_extension: false,
+                ..SearchOptions::default()
+            },
+            SearchLimits {
+                max_depth: Some(1),
+                ..SearchLimits::default()
+            },
+            vec![],
+        );
+        assert_eq!(best_move.unwrap().to_notation(), "e2-c4");
+        let (mate, quiescence_nodes) = last.get();
+        assert_eq!(mate, Some(3));
+        assert!(quiescence_nodes > 0);
+    }
+}
+
+#[test]
+fn test_pvs_matches_alpha_beta() {
+    use board::BoardState;
+    use std::cell::RefCell;
+
+    let search = |board, algorithm| {
+        let score = RefCell::new(None);
+        let best_move = find_best_move(
+            board,
+            || false,
+            |partial| *score.borrow_mut() = Some(partial.result.score),
+            // Extended lines reuse table entries searched to other depths
+            // along other lines, so the algorithms would agree only up to
+            // the order they search in.
+            SearchOptions {
+                algorithm,
+                tt_size_mb: 1,
+                threat_extension: false,
+                ..SearchOptions::default()
+            },
+            SearchLimits {
+                max_depth: Some(4),
+                ..SearchLimits::default()
+            },
+            vec![],
+        );
+        (score.into_inner(), best_move.is_some())
+    };
+    for _ in 0..100 {
+        let (state, player) = BoardState::random();
+        let board = state.to_board2(player);
+        assert_eq!(
+            search(board, SearchAlgorithm::Pvs),
+            search(board, SearchAlgorithm::AlphaBeta),
+            "{}",
+            board.to_notation()
+        );
+    }
+}
+
+#[test]
+fn test_multi_pv() {
+    use std::cell::RefCell;
+
+    let board = Board2::new_with_king_inversed();
+    let lines = RefCell::new(vec![]);
+    let best_move = find_best_move(
+        board,
+        || false,
+        |partial| *lines.borrow_mut() = partial.lines,
+        SearchOptions {
+            multi_pv: 3,
+            ..SearchOptions::default()
+        },
+        SearchLimits {
+            max_depth: Some(5),
+            ..SearchLimits::default()
+        },
+        vec![],
+    );
+    let lines = lines.into_inner();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0].best_path.last().copied(), best_move);
+    let first_moves = lines
+        .iter()
+        .map(|line| *line.best_path.last().unwrap())
+        .collect::<std::collections::HashSet<_>>();
+    assert_eq!(first_moves.len(), 3);
+    assert!(lines.windows(2).all(|w| w[0].score >= w[1].score));
+}
+
+#[test]
+fn test_threaded_principal_variations() {
+    use std::cell::RefCell;
+
+    // Helpers fill the table with exact scores, which must not cut the
+    // lines short.
+    let board = Board2::new_with_king_inversed();
+    let partials = RefCell::new(vec![]);
+    find_best_move(
+        board,
+        || false,
+        |partial| partials.borrow_mut().push(partial.lines),
+        SearchOptions {
+            threads: 4,
+            multi_pv: 3,
+            ..SearchOptions::default()
+        },
+        SearchLimits {
+            max_depth: Some(8),
+            ..SearchLimits::default()
+        },
+        vec![],
+    );
+    for line in partials.into_inner().into_iter().flatten() {
+        let end = line
+            .best_path
+            .iter()
+            .rev()
+            .fold(board, |board, &m| board.do_move(m));
+        assert!(
+            line.best_path.len() >= line.depth || end.ended(),
+            "{} plies at depth {}",
+            line.best_path.len(),
+            line.depth
+        );
+    }
+}
+
+#[test]
+fn test_ponder() {
+    use std::cell::Cell;
+
+    // The depth limit is ignored until the ponder hit, which happens once
+    // depth 4 is completed; the search then stops instead of deepening.
+    let hit = Arc::new(AtomicBool::new(false));
+    let depths = Cell::new(0);
+    let best_move = ponder(
+        Board2::new_with_king_inversed(),
+        || false,
+        {
+            let hit = hit.clone();
+            move || hit.load(Ordering::Relaxed)
+        },
+        |partial| {
+            depths.set(partial.depth);
+            if partial.depth == 4 {
+                hit.store(true, Ordering::Relaxed);
+            }
+        },
+        SearchOptions::default(),
+        SearchLimits {
+            max_depth: Some(2),
+            ..SearchLimits::default()
+        },
+        vec![],
+    );
+    assert!(best_move.is_some());
+    assert_eq!(depths.get(), 4);
+}
+
+#[test]
+fn test_searcher_reuses_table() {
+    use std::cell::Cell;
+
+    let board = Board2::new_with_king_inversed();
+    let nodes = |searcher: &mut Searcher| {
+        let nodes = Cell::new(0);
+        searcher.find_best_move(
+            board,
+            || false,
+            |partial| nodes.set(nodes.get() + partial.nodes_searched),
+            SearchLimits {
+                max_depth: Some(6),
+                ..SearchLimits::default()
+            },
+            vec![],
+        );
+        nodes.get()
+    };
+    let mut searcher = Searcher::new(SearchOptions::default());
+    let fresh = nodes(&mut searcher);
+    assert!(nodes(&mut searcher) < fresh);
+    searcher.new_game();
+    assert_eq!(searcher.transposition_table_size(), 0);
+    assert_eq!(nodes(&mut searcher), fresh);
+
+    // Scores under one repetition rule are wrong under another.
+    let options = searcher.options();
+    searcher.set_options(SearchOptions {
+        multi_pv: 2,
+        ..options
+    });
+    assert!(searcher.transposition_table_size() > 0);
+    searcher.set_options(SearchOptions {
+        repetition_rule: RepetitionRule::DrawOnThreefold,
+        ..options
+    });
+    assert_eq!(searcher.transposition_table_size(), 0);
+}
+
+#[test]
+fn test_custom_evaluator() {
+    use std::cell::Cell;
+
+    // Every position that has not ended is even for this evaluator.
+    struct Even;
+
+    impl Evaluator for Even {
+        fn evaluate(&self, _: Board2) -> i32 {
+            DRAW_SCORE
+        }
+    }
+
+    let score = Cell::new(None);
+    let mut searcher = Searcher::with_evaluator(SearchOptions::default(), Even);
+    let best_move = searcher.find_best_move(
+        Board2::new_original(),
+        || false,
+        |partial| score.set(Some(partial.result.score)),
+        SearchLimits {
+            max_depth: Some(4),
+            ..SearchLimits::default()
+        },
+        vec![],
+    );
+    assert!(best_move.is_some());
+    assert_eq!(score.get(), Some(DRAW_SCORE));
+}
@@ -0,0 +1,369 @@
+//! A compact, human-readable notation for positions, similar to FEN.
+//!
+//! The five board rows are listed from row 0 (squares 0 to 4) to row 4
+//! (squares 20 to 24), separated by `/`. Within a row, `P` and `K` are the
+//! white pawns and king, `p` and `k` the black ones, and a digit from 1 to 5
+//! stands for that many empty squares. The rows are followed by a space and
+//! the side to move, `w` or `b`. The original starting position is
+//!
+//! ```text
+//! PPKPP/5/5/5/ppkpp w
+//! ```
+//!
+//! Squares are named by a column letter from `a` to `e` and a rank from `1`
+//! (row 0) to `5` (row 4), so square 0 is `a1`, the centre square 12 is `c3`
+//! and square 24 is `e5`. A move is written as its from and to squares, e.g.
+//! `c1-c3`; plain square numbers such as `2-12` are accepted as well.
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::{
+    board2::{Board2, Move, PositionError},
+    cell::CellState,
+    player::Player,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NotationError {
+    WrongRowCount(usize),
+    RowTooShort {
+        row: usize,
+        length: usize,
+    },
+    RowTooLong {
+        row: usize,
+    },
+    UnexpectedCharacter {
+        row: usize,
+        column: usize,
+        found: char,
+    },
+    WrongPieceCount {
+        piece: char,
+        expected: usize,
+        found: usize,
+    },
+    InvalidPosition(PositionError),
+    MissingSideToMove,
+    InvalidSideToMove(String),
+    TrailingInput(String),
+}
+
+impl Display for NotationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongRowCount(n) => write!(f, "expected 5 rows but found {}", n),
+            Self::RowTooShort { row, length } => {
+                write!(f, "row {} covers {} squares instead of 5", row, length)
+            }
+            Self::RowTooLong { row } => write!(f, "row {} covers more than 5 squares", row),
+            Self::UnexpectedCharacter { row, column, found } => write!(
+                f,
+                "unexpected character '{}' at row {}, column {}",
+                found, row, column
+            ),
+            Self::WrongPieceCount {
+                piece,
+                expected,
+                found,
+            } => write!(f, "expected {} '{}' but found {}", expected, piece, found),
+            Self::InvalidPosition(e) => write!(f, "{}", e),
+            Self::MissingSideToMove => write!(f, "missing side to move"),
+            Self::InvalidSideToMove(s) => {
+                write!(f, "side to move must be 'w' or 'b', not '{}'", s)
+            }
+            Self::TrailingInput(s) => write!(f, "unexpected trailing input '{}'", s),
+        }
+    }
+}
+
+impl std::error::Error for NotationError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MoveError {
+    Malformed(String),
+    InvalidSquare(String),
+    NoPieceToMove(u8),
+    Illegal { from: u8, to: u8 },
+}
+
+impl Display for MoveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed(s) => write!(f, "'{}' is not of the form <from>-<to>", s),
+            Self::InvalidSquare(s) => write!(f, "'{}' is not a square", s),
+            Self::NoPieceToMove(square) => write!(
+                f,
+                "no piece of the side to move on {}",
+                square_name(*square)
+            ),
+            Self::Illegal { from, to } => write!(
+                f,
+                "{}-{} is not a legal move",
+                square_name(*from),
+                square_name(*to)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MoveError {}
+
+pub fn square_name(square: u8) -> String {
+    let column = (b'a' + square % 5) as char;
+    format!("{}{}", column, square / 5 + 1)
+}
+
+/// Parses a square name such as `c3`, or a square number from 0 to 24.
+pub fn parse_square(s: &str) -> Result<u8, MoveError> {
+    let invalid = || MoveError::InvalidSquare(s.to_string());
+    if let Ok(square) = s.parse::<u8>() {
+        return if square < 25 {
+            Ok(square)
+        } else {
+            Err(invalid())
+        };
+    }
+    match s.as_bytes() {
+        [column @ b'a'..=b'e', rank @ b'1'..=b'5'] => Ok((rank - b'1') * 5 + (column - b'a')),
+        _ => Err(invalid()),
+    }
+}
+
+impl Move {
+    pub fn to_notation(&self) -> String {
+        format!("{}-{}", square_name(self.from()), square_name(self.to()))
+    }
+}
+
+fn piece_char(cell: CellState) -> Option<char> {
+    match cell {
+        CellState::Empty => None,
+        CellState::WhitePawn => Some('P'),
+        CellState::BlackPawn => Some('p'),
+        CellState::WhiteKing => Some('K'),
+        CellState::BlackKing => Some('k'),
+    }
+}
+
+impl Board2 {
+    pub fn to_notation(self) -> String {
+        let board = self.flatten();
+        let mut s = String::new();
+        for row in 0..5 {
+            if row > 0 {
+                s.push('/');
+            }
+            let mut empty = 0;
+            for col in 0..5 {
+                match piece_char(board[row * 5 + col]) {
+                    Some(c) => {
+                        if empty > 0 {
+                            s.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        s.push(c);
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                s.push_str(&empty.to_string());
+            }
+        }
+        s.push_str(if self.maximizing() { " w" } else { " b" });
+        s
+    }
+
+    pub fn parse_notation(s: &str) -> Result<Board2, NotationError> {
+        let mut parts = s.split_whitespace();
+        let rows = parts.next().unwrap_or("").split('/').collect::<Vec<_>>();
+        if rows.len() != 5 {
+            return Err(NotationError::WrongRowCount(rows.len()));
+        }
+
+        let mut white_pawns = Vec::new();
+        let mut black_pawns = Vec::new();
+        let mut white_kings = Vec::new();
+        let mut black_kings = Vec::new();
+        for (row, text) in rows.iter().enumerate() {
+            let mut column = 0;
+            for c in text.chars() {
+                if column >= 5 {
+                    return Err(NotationError::RowTooLong { row });
+                }
+                let square = (row * 5 + column) as u8;
+                match c {
+                    '1'..='5' => column += c.to_digit(10).unwrap() as usize,
+                    'P' | 'p' | 'K' | 'k' => {
+                        let pieces = match c {
+                            'P' => &mut white_pawns,
+                            'p' => &mut black_pawns,
+                            'K' => &mut white_kings,
+                            _ => &mut black_kings,
+                        };
+                        pieces.push(square);
+                        column += 1;
+                    }
+                    _ => {
+                        return Err(NotationError::UnexpectedCharacter {
+                            row,
+                            column,
+                            found: c,
+                        })
+                    }
+                }
+            }
+            if column > 5 {
+                return Err(NotationError::RowTooLong { row });
+            }
+            if column < 5 {
+                return Err(NotationError::RowTooShort {
+                    row,
+                    length: column,
+                });
+            }
+        }
+
+        for (piece, squares, expected) in [
+            ('P', &white_pawns, 4),
+            ('p', &black_pawns, 4),
+            ('K', &white_kings, 1),
+            ('k', &black_kings, 1),
+        ] {
+            if squares.len() != expected {
+                return Err(NotationError::WrongPieceCount {
+                    piece,
+                    expected,
+                    found: squares.len(),
+                });
+            }
+        }
+        let side_to_move = match parts.next() {
+            Some("w") => 1,
+            Some("b") => 0,
+            Some(s) => return Err(NotationError::InvalidSideToMove(s.to_string())),
+            None => return Err(NotationError::MissingSideToMove),
+        };
+        if let Some(rest) = parts.next() {
+            return Err(NotationError::TrailingInput(rest.to_string()));
+        }
+
+        let mut positions = white_pawns;
+        positions.extend(black_pawns);
+        positions.extend(white_kings);
+        positions.extend(black_kings);
+        positions.push(side_to_move);
+        Board2::try_from_positions(&positions).map_err(NotationError::InvalidPosition)
+    }
+
+    /// Resolves a move written as `<from>-<to>` into the legal move of the
+    /// side to move between those squares.
+    pub fn parse_move(self, s: &str) -> Result<Move, MoveError> {
+        let (from, to) = s
+            .trim()
+            .split_once('-')
+            .ok_or_else(|| MoveError::Malformed(s.to_string()))?;
+        let from = parse_square(from)?;
+        let to = parse_square(to)?;
+        let player = if self.maximizing() {
+            Player::White
+        } else {
+            Player::Black
+        };
+        if !self.flatten()[from as usize].is_player(player) {
+            return Err(MoveError::NoPieceToMove(from));
+        }
+        self.legal_moves()
+            .into_iter()
+            .map(|(m, _)| m)
+            .find(|m| m.to() == to && m.from() == from)
+            .ok_or(MoveError::Illegal { from, to })
+    }
+}
+
+#[test]
+fn test_notation_round_trip() {
+    use crate::board::BoardState;
+
+    assert_eq!(Board2::new_original().to_notation(), "PPKPP/5/5/5/ppkpp w");
+    assert_eq!(
+        Board2::parse_notation("PPkPP/5/5/5/ppKpp w"),
+        Ok(Board2::new_with_king_inversed())
+    );
+    for _ in 0..1000 {
+        let (state, player) = BoardState::random();
+        let board = state.to_board2(player);
+        assert_eq!(Board2::parse_notation(&board.to_notation()), Ok(board));
+        let board = state.to_board2(player.opponent());
+        assert_eq!(Board2::parse_notation(&board.to_notation()), Ok(board));
+    }
+}
+
+#[test]
+fn test_notation_errors() {
+    assert_eq!(
+        Board2::parse_notation("PPKPP/5/5/ppkpp w"),
+        Err(NotationError::WrongRowCount(4))
+    );
+    assert_eq!(
+        Board2::parse_notation("PPKPP/5/2x2/5/ppkpp w"),
+        Err(NotationError::UnexpectedCharacter {
+            row: 2,
+            column: 2,
+            found: 'x'
+        })
+    );
+    assert_eq!(
+        Board2::parse_notation("PPKPP/4/5/5/ppkpp w"),
+        Err(NotationError::RowTooShort { row: 1, length: 4 })
+    );
+    assert_eq!(
+        Board2::parse_notation("PPKPP/5/5/5/ppkpp1 w"),
+        Err(NotationError::RowTooLong { row: 4 })
+    );
+    assert_eq!(
+        Board2::parse_notation("PPKP1/5/5/5/ppkpp w"),
+        Err(NotationError::WrongPieceCount {
+            piece: 'P',
+            expected: 4,
+            found: 3
+        })
+    );
+    assert_eq!(
+        Board2::parse_notation("1PKPP/5/2P2/5/ppkpp w"),
+        Err(NotationError::InvalidPosition(PositionError::PawnOnCentre(
+            3
+        )))
+    );
+    assert_eq!(
+        Board2::parse_notation("PPKPP/5/5/5/ppkpp"),
+        Err(NotationError::MissingSideToMove)
+    );
+    assert_eq!(
+        Board2::parse_notation("PPKPP/5/5/5/ppkpp x"),
+        Err(NotationError::InvalidSideToMove("x".to_string()))
+    );
+}
+
+#[test]
+fn test_move_notation() {
+    let board = Board2::new_original();
+    let m = board.parse_move("c1-c4").unwrap();
+    assert_eq!(m.to_notation(), "c1-c4");
+    assert_eq!(board.parse_move("2-17"), Ok(m));
+    assert_eq!(board.do_move(m).to_notation(), "PP1PP/5/5/2K2/ppkpp b");
+    assert_eq!(
+        board.parse_move("c1-c3"),
+        Err(MoveError::Illegal { from: 2, to: 12 })
+    );
+    assert_eq!(board.parse_move("c5-c4"), Err(MoveError::NoPieceToMove(22)));
+    assert_eq!(
+        board.parse_move("c1-f4"),
+        Err(MoveError::InvalidSquare("f4".to_string()))
+    );
+    assert_eq!(
+        board.parse_move("c1c4"),
+        Err(MoveError::Malformed("c1c4".to_string()))
+    );
+}
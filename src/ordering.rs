@@ -0,0 +1,92 @@
+//! Move ordering heuristics learned during a search.
+//!
+//! Killer moves are the last two moves that caused a cutoff at each ply; as
+//! sibling positions tend to have the same refutation, they are tried early.
+//! The history table scores every (piece, from, to) combination by how often
+//! and how deep it caused a cutoff anywhere in the tree.
+
+use crate::{board2::Move, cell::CellState};
+
+pub struct MoveOrdering {
+    killers: Vec<[Option<Move>; 2]>,
+    history: Vec<u32>,
+}
+
+fn history_index(m: Move) -> usize {
+    let piece = match m.piece() {
+        CellState::WhitePawn => 0,
+        CellState::BlackPawn => 1,
+        CellState::WhiteKing => 2,
+        _ => 3,
+    };
+    (piece * 25 + m.from() as usize) * 25 + m.to() as usize
+}
+
+impl MoveOrdering {
+    pub fn new() -> MoveOrdering {
+        MoveOrdering {
+            killers: Vec::new(),
+            history: vec![0; 4 * 25 * 25],
+        }
+    }
+
+    /// 0 or 1 for the killer moves at `ply`, in order of preference.
+    pub fn killer_rank(&self, m: Move, ply: usize) -> Option<usize> {
+        self.killers
+            .get(ply)
+            .and_then(|killers| killers.iter().position(|k| *k == Some(m)))
+    }
+
+    pub fn history(&self, m: Move) -> u32 {
+        self.history[history_index(m)]
+    }
+
+    /// Records that `m` caused a cutoff at `ply` with `remaining_depth` plies
+    /// left to search.
+    pub fn record_cutoff(&mut self, m: Move, ply: usize, remaining_depth: usize) {
+        if self.killers.len() <= ply {
+            self.killers.resize(ply + 1, [None; 2]);
+        }
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(m) {
+            killers[1] = killers[0];
+            killers[0] = Some(m);
+        }
+        let bonus = (remaining_depth * remaining_depth) as u32;
+        let entry = &mut self.history[history_index(m)];
+        *entry = entry.saturating_add(bonus);
+    }
+
+    /// Prepares for searching another position. Killer moves are tied to
+    /// plies from the root and are forgotten; the history carries over.
+    pub fn new_search(&mut self) {
+        self.killers.clear();
+    }
+
+    /// Halves the history scores, so that cutoffs at the current depth weigh
+    /// more than those of earlier iterations.
+    pub fn age(&mut self) {
+        self.history.iter_mut().for_each(|h| *h /= 2);
+    }
+}
+
+#[test]
+fn test_killers_and_history() {
+    use crate::board2::Board2;
+
+    let moves = Board2::new_original().legal_moves();
+    let (a, b, c) = (moves[0].0, moves[1].0, moves[2].0);
+    let mut ordering = MoveOrdering::new();
+    ordering.record_cutoff(a, 3, 2);
+    ordering.record_cutoff(b, 3, 3);
+    ordering.record_cutoff(b, 3, 3);
+    assert_eq!(ordering.killer_rank(b, 3), Some(0));
+    assert_eq!(ordering.killer_rank(a, 3), Some(1));
+    assert_eq!(ordering.killer_rank(a, 2), None);
+    ordering.record_cutoff(c, 3, 1);
+    assert_eq!(ordering.killer_rank(a, 3), None);
+    assert_eq!(ordering.history(a), 4);
+    assert_eq!(ordering.history(b), 18);
+    ordering.age();
+    assert_eq!(ordering.history(b), 9);
+}
@@ -0,0 +1,88 @@
+//! Move path enumeration for checking the move generator.
+//!
+//! `perft(depth)` counts the move sequences of exactly `depth` plies from a
+//! position, following every legal move (`Board2::legal_moves`, so winning
+//! moves do not hide the others). A game ends when a king reaches the centre,
+//! so such positions have no continuations.
+
+use crate::board2::{Board2, Move};
+
+/// `perft` of `Board2::new_original()` for depths 1 to 7. Depths up to 6 are
+/// confirmed by the independent `BoardState` move generator.
+pub const PERFT_ORIGINAL: [u64; 7] = [13, 146, 2130, 29706, 435170, 6176356, 92225026];
+
+/// `perft` of `Board2::new_with_king_inversed()` for depths 1 to 7.
+pub const PERFT_KING_INVERSED: [u64; 7] = [13, 164, 2312, 31594, 456432, 6463044, 95435722];
+
+/// The checked-in `perft` result for `board` at `depth`, if there is one.
+pub fn expected_perft(board: Board2, depth: usize) -> Option<u64> {
+    let table = if board == Board2::new_original() {
+        &PERFT_ORIGINAL
+    } else if board == Board2::new_with_king_inversed() {
+        &PERFT_KING_INVERSED
+    } else {
+        return None;
+    };
+    depth.checked_sub(1).and_then(|i| table.get(i)).copied()
+}
+
+impl Board2 {
+    pub fn perft(self, depth: usize) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let moves = self.legal_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        moves
+            .into_iter()
+            .map(|(_, next)| next.perft(depth - 1))
+            .sum()
+    }
+
+    /// `perft` split by the first move.
+    pub fn perft_divide(self, depth: usize) -> Vec<(Move, u64)> {
+        if depth == 0 {
+            return vec![];
+        }
+        self.legal_moves()
+            .into_iter()
+            .map(|(m, next)| (m, next.perft(depth - 1)))
+            .collect()
+    }
+}
+
+#[test]
+fn test_perft_table() {
+    for board in [Board2::new_original(), Board2::new_with_king_inversed()] {
+        for depth in 1..=5 {
+            assert_eq!(Some(board.perft(depth)), expected_perft(board, depth));
+        }
+        let divided = board.perft_divide(3);
+        assert_eq!(divided.len() as u64, board.perft(1));
+        assert_eq!(divided.iter().map(|(_, n)| n).sum::<u64>(), board.perft(3));
+    }
+}
+
+#[test]
+fn test_perft_matches_board_state() {
+    use crate::{board::BoardState, player::Player};
+
+    fn board_state_perft(state: &BoardState, player: Player, depth: usize) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        state
+            .next_moves(player)
+            .iter()
+            .map(|next| board_state_perft(next, player.opponent(), depth - 1))
+            .sum()
+    }
+
+    for _ in 0..20 {
+        let (state, player) = BoardState::random();
+        let board = state.to_board2(player);
+        assert_eq!(board.perft(3), board_state_perft(&state, player, 3));
+    }
+}
@@ -0,0 +1,566 @@
+//! A line-based engine protocol modelled after UCI, so that GUIs and
+//! tournament tools can drive the engine as a subprocess.
+//!
+//! Commands read from the input, one per line:
+//!
+//! ```text
+//! uci
+//! isready
+//! ucinewgame
+//! setoption name <name> value <value>
+//! position (start | inversed | notation <rows> <side> | positions <p0,...,p10>) [moves <move> ...]
+//! go [ponder] [depth <n>] [movetime <ms>] [nodes <n>] [infinite]
+//! ponderhit
+//! stop
+//! quit
+//! ```
+//!
+//! While searching, the engine writes one `info` line per completed depth,
+//! with `nodes` searched to the depth and `qnodes` searched beyond it for
+//! threats to win, and finally `bestmove <move> [ponder <reply>]` (or `bestmove none`), the
+//! reply being the one expected by the principal variation. Moves use the square
+//! notation described in `notation`, e.g. `c1-c4`. Scores are reported from
+//! the point of view of the side to move, as `cp <score>`, or as
+//! `mate <moves>` when a forced win is found, negative if the side to move
+//! is the one losing.
+//!
+//! `go ponder` searches the position after the expected reply on the
+//! opponent's time, as set up by the GUI, ignoring the limits. On
+//! `ponderhit` the same search continues with the limits counted from then;
+//! after `stop` it reports its move as usual. A pondering search does not
+//! report its move on its own.
+//!
+//! Options:
+//!
+//! - `RepetitionRule`: `forbidden` (default), `draw_on_threefold` or
+//!   `loss_for_repeater`, see `game::RepetitionRule`.
+//! - `Hash`: size of the transposition table in megabytes.
+//! - `Threads`: number of threads searching in parallel.
+//! - `MultiPV`: number of principal variations to report, each as an `info`
+//!   line with `multipv <n>`.
+//! - `Weights`: path of a JSON file with evaluation weights, see `eval`.
+
+use std::{
+    cell::Cell,
+    io::{BufRead, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    board2::{Board2, Move},
+    eval::TableEvaluator,
+    game::{Game, GameResult},
+    PartialSearchResult, SearchLimits, SearchOptions, Searcher, DEFAULT_TT_SIZE_MB,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GoLimits {
+    pub depth: Option<usize>,
+    pub movetime: Option<Duration>,
+    pub nodes: Option<usize>,
+    pub infinite: bool,
+    pub ponder: bool,
+}
+
+impl GoLimits {
+    /// The limits of the search itself. `infinite` ignores all other limits.
+    pub fn search_limits(&self) -> SearchLimits {
+        if self.infinite {
+            return SearchLimits::default();
+        }
+        SearchLimits {
+            max_depth: self.depth,
+            max_nodes: self.nodes,
+            soft_time_ms: None,
+            hard_time_ms: self.movetime.map(|t| t.as_millis() as u64),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Uci,
+    IsReady,
+    NewGame,
+    SetOption { name: String, value: String },
+    Position { start: Board2, moves: Vec<String> },
+    Go(GoLimits),
+    PonderHit,
+    Stop,
+    Quit,
+}
+
+/// Parses a position given as `start`, `inversed`, position notation (see
+/// `notation`) or the 11 comma separated values taken by
+/// `Board2::try_from_positions`.
+pub fn parse_position(s: &str) -> Result<Board2, String> {
+    match s {
+        "start" => Ok(Board2::new_original()),
+        "inversed" => Ok(Board2::new_with_king_inversed()),
+        _ if s.contains('/') => Board2::parse_notation(s).map_err(|e| e.to_string()),
+        _ => {
+            let positions = s
+                .split(',')
+                .map(|p| p.trim().parse::<u8>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| format!("bad position `{}`", s))?;
+            Board2::try_from_positions(&positions).map_err(|e| e.to_string())
+        }
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: Option<&str>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("missing value for {}", name))?;
+    value
+        .parse()
+        .map_err(|_| format!("bad value `{}` for {}", value, name))
+}
+
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    let mut tokens = line.split_whitespace();
+    let command = match tokens.next() {
+        Some(command) => command,
+        None => return Err("empty command".to_string()),
+    };
+    match command {
+        "uci" => Ok(Command::Uci),
+        "isready" => Ok(Command::IsReady),
+        "ucinewgame" => Ok(Command::NewGame),
+        "ponderhit" => Ok(Command::PonderHit),
+        "stop" => Ok(Command::Stop),
+        "quit" => Ok(Command::Quit),
+        "setoption" => {
+            if tokens.next() != Some("name") {
+                return Err("expected `setoption name <name> value <value>`".to_string());
+            }
+            let name = tokens.next().ok_or("missing option name")?.to_string();
+            if tokens.next() != Some("value") {
+                return Err("expected `setoption name <name> value <value>`".to_string());
+            }
+            let value = tokens.collect::<Vec<_>>().join(" ");
+            Ok(Command::SetOption { name, value })
+        }
+        "position" => {
+            let start = match tokens.next() {
+                Some("positions") => parse_position(tokens.next().ok_or("missing positions")?)?,
+                Some("notation") => {
+                    let rows = tokens.next().ok_or("missing notation")?;
+                    let side = tokens.next().ok_or("missing side to move")?;
+                    Board2::parse_notation(&format!("{} {}", rows, side))
+                        .map_err(|e| e.to_string())?
+                }
+                Some(position) => parse_position(position)?,
+                None => return Err("missing position".to_string()),
+            };
+            let moves = match tokens.next() {
+                Some("moves") => tokens.map(|m| m.to_string()).collect(),
+                Some(token) => return Err(format!("unexpected `{}`", token)),
+                None => vec![],
+            };
+            Ok(Command::Position { start, moves })
+        }
+        "go" => {
+            let mut limits = GoLimits::default();
+            while let Some(token) = tokens.next() {
+                match token {
+                    "depth" => limits.depth = Some(parse_number("depth", tokens.next())?),
+                    "movetime" => {
+                        let ms = parse_number("movetime", tokens.next())?;
+                        limits.movetime = Some(Duration::from_millis(ms));
+                    }
+                    "nodes" => limits.nodes = Some(parse_number("nodes", tokens.next())?),
+                    "infinite" => limits.infinite = true,
+                    "ponder" => limits.ponder = true,
+                    _ => return Err(format!("unexpected `{}`", token)),
+                }
+            }
+            Ok(Command::Go(limits))
+        }
+        _ => Err(format!("unknown command `{}`", command)),
+    }
+}
+
+fn format_score(board: Board2, score: i32, mate: Option<i32>) -> String {
+    let sign = if board.maximizing() { 1 } else { -1 };
+    match mate {
+        // UCI counts moves of the side to move rather than plies.
+        Some(plies) if plies * sign > 0 => format!("mate {}", (plies.abs() + 1) / 2),
+        Some(plies) => format!("mate -{}", plies.abs() / 2),
+        None => format!("cp {}", score * sign),
+    }
+}
+
+fn format_pv(best_path: &[Move]) -> String {
+    best_path
+        .iter()
+        .rev()
+        .map(|m| m.to_notation())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Formats a completed iteration as an `info` line, or one line per
+/// principal variation of a multi-PV search.
+pub fn format_info(board: Board2, partial: &PartialSearchResult, elapsed: Duration) -> String {
+    if partial.lines.len() <= 1 {
+        return format!(
+            "info depth {} score {} nodes {} qnodes {} time {} pv {}",
+            partial.depth,
+            format_score(board, partial.result.score, partial.mate),
+            partial.nodes_searched,
+            partial.quiescence_nodes,
+            elapsed.as_millis(),
+            format_pv(&partial.result.best_path)
+        );
+    }
+    partial
+        .lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            format!(
+                "info depth {} multipv {} score {} nodes {} qnodes {} time {} pv {}",
+                line.depth,
+                i + 1,
+                format_score(board, line.score, line.mate),
+                partial.nodes_searched,
+                partial.quiescence_nodes,
+                elapsed.as_millis(),
+                format_pv(&line.best_path)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+struct Search {
+    stop: Arc<AtomicBool>,
+    /// Set on `ponderhit`, for pondering searches.
+    ponder_hit: Option<Arc<AtomicBool>>,
+    infinite: bool,
+    handle: JoinHandle<()>,
+}
+
+pub struct Session<W: Write + Send + 'static> {
+    output: Arc<Mutex<W>>,
+    game: Game,
+    options: SearchOptions,
+    /// Kept for the whole game, so that each search reuses what earlier ones
+    /// learned; reset by `ucinewgame`.
+    searcher: Arc<Mutex<Searcher>>,
+    /// Weights loaded by `setoption`, given to the searcher by the next
+    /// search.
+    weights: Option<TableEvaluator>,
+    search: Option<Search>,
+}
+
+impl<W: Write + Send + 'static> Session<W> {
+    pub fn new(output: W) -> Session<W> {
+        Session {
+            output: Arc::new(Mutex::new(output)),
+            game: Game::new(Board2::new_original()),
+            options: SearchOptions::default(),
+            searcher: Arc::new(Mutex::new(Searcher::new(SearchOptions::default()))),
+            weights: None,
+            search: None,
+        }
+    }
+
+    fn write_line(&self, line: &str) {
+        let mut output = self.output.lock().unwrap();
+        writeln!(output, "{}", line).unwrap();
+        output.flush().unwrap();
+    }
+
+    /// Handles one input line. Returns false once the session should end.
+    pub fn handle_line(&mut self, line: &str) -> bool {
+        if line.trim().is_empty() {
+            return true;
+        }
+        let command = match parse_command(line) {
+            Ok(command) => command,
+            Err(e) => {
+                self.write_line(&format!("info string error: {}", e));
+                return true;
+            }
+        };
+        match command {
+            Command::Uci => {
+                self.write_line("id name penguin");
+                self.write_line("id author hpmv");
+                self.write_line(
+                    "option name RepetitionRule type combo default forbidden \
+                     var forbidden var draw_on_threefold var loss_for_repeater",
+                );
+                self.write_line(&format!(
+                    "option name Hash type spin default {} min 1 max 65536",
+                    DEFAULT_TT_SIZE_MB
+                ));
+                self.write_line("option name Threads type spin default 1 min 1 max 1024");
+                self.write_line("option name MultiPV type spin default 1 min 1 max 32");
+                self.write_line("option name Weights type string default <empty>");
+                self.write_line("uciok");
+            }
+            Command::IsReady => self.write_line("readyok"),
+            Command::NewGame => {
+                self.stop();
+                self.searcher.lock().unwrap().new_game();
+                self.game = Game::with_repetition_rule(
+                    Board2::new_original(),
+                    self.options.repetition_rule,
+                );
+            }
+            Command::SetOption { name, value } => match name.as_str() {
+                "RepetitionRule" => match value.parse() {
+                    Ok(rule) => self.options.repetition_rule = rule,
+                    Err(e) => self.write_line(&format!("info string error: {}", e)),
+                },
+                "Hash" => match value.parse() {
+                    Ok(size_mb) if size_mb > 0 => self.options.tt_size_mb = size_mb,
+                    _ => self.write_line(&format!("info string error: bad size `{}`", value)),
+                },
+                "Threads" => match value.parse() {
+                    Ok(threads) if threads > 0 => self.options.threads = threads,
+                    _ => {
+                        self.write_line(&format!("info string error: bad thread count `{}`", value))
+                    }
+                },
+                "MultiPV" => match value.parse() {
+                    Ok(lines) if lines > 0 => self.options.multi_pv = lines,
+                    _ => self.write_line(&format!("info string error: bad line count `{}`", value)),
+                },
+                "Weights" => match TableEvaluator::load(&value) {
+                    Ok(weights) => self.weights = Some(weights),
+                    Err(e) => self.write_line(&format!("info string error: {}: {}", value, e)),
+                },
+                _ => self.write_line(&format!("info string error: unknown option `{}`", name)),
+            },
+            Command::Position { start, moves } => {
+                self.stop();
+                let mut game = Game::with_repetition_rule(start, self.options.repetition_rule);
+                for m in moves.iter() {
+                    if let Err(e) = game.play_notation(m) {
+                        self.write_line(&format!("info string error: {}: {}", m, e));
+                        return true;
+                    }
+                }
+                self.game = game;
+            }
+            Command::Go(limits) => {
+                self.stop();
+                self.go(limits);
+            }
+            Command::PonderHit => {
+                match self
+                    .search
+                    .as_ref()
+                    .and_then(|search| search.ponder_hit.as_ref())
+                {
+                    Some(ponder_hit) => ponder_hit.store(true, Ordering::Relaxed),
+                    None => self.write_line("info string error: not pondering"),
+                }
+            }
+            Command::Stop => self.stop(),
+            Command::Quit => {
+                self.stop();
+                return false;
+            }
+        }
+        true
+    }
+
+    fn go(&mut self, limits: GoLimits) {
+        let stop = Arc::new(AtomicBool::new(false));
+        let board = self.game.current();
+        let history = self.game.positions().to_vec();
+        let finished = self.game.result() != GameResult::Ongoing;
+        let options = self.options;
+        let output = self.output.clone();
+        let searcher = self.searcher.clone();
+        let weights = if finished { None } else { self.weights.take() };
+        let search_stop = stop.clone();
+        let ponder_hit = limits.ponder.then(|| Arc::new(AtomicBool::new(false)));
+        let search_ponder_hit = ponder_hit.clone();
+        let handle = thread::spawn(move || {
+            if finished {
+                let mut output = output.lock().unwrap();
+                writeln!(output, "bestmove none").unwrap();
+                output.flush().unwrap();
+                return;
+            }
+            let start = Instant::now();
+            let should_stop = {
+                let stop = search_stop.clone();
+                move || stop.load(Ordering::Relaxed)
+            };
+            let expected_reply = Cell::new(None);
+            let partial = |partial: PartialSearchResult| {
+                expected_reply.set(partial.result.expected_reply());
+                let line = format_info(board, &partial, start.elapsed());
+                let mut output = output.lock().unwrap();
+                writeln!(output, "{}", line).unwrap();
+                output.flush().unwrap();
+            };
+            // Options are set between searches, so that `setoption` never
+            // waits for a running one.
+            let mut searcher = searcher.lock().unwrap();
+            searcher.set_options(options);
+            if let Some(weights) = weights {
+                searcher.set_evaluator(weights);
+            }
+            let best_move = match &search_ponder_hit {
+                Some(ponder_hit) => searcher.ponder(
+                    board,
+                    should_stop,
+                    || ponder_hit.load(Ordering::Relaxed),
+                    partial,
+                    limits.search_limits(),
+                    history,
+                ),
+                None => searcher.find_best_move(
+                    board,
+                    should_stop,
+                    partial,
+                    limits.search_limits(),
+                    history,
+                ),
+            };
+            drop(searcher);
+            // Infinite searches only report their move once told to stop,
+            // pondering ones once told to stop or after the ponder hit.
+            let waiting = || {
+                let pondering = search_ponder_hit
+                    .as_ref()
+                    .is_some_and(|ponder_hit| !ponder_hit.load(Ordering::Relaxed));
+                (limits.infinite || pondering) && !search_stop.load(Ordering::Relaxed)
+            };
+            while waiting() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            let mut line = match best_move {
+                Some(m) => format!("bestmove {}", m.to_notation()),
+                None => "bestmove none".to_string(),
+            };
+            if let (Some(_), Some(reply)) = (best_move, expected_reply.get()) {
+                line.push_str(&format!(" ponder {}", reply.to_notation()));
+            }
+            let mut output = output.lock().unwrap();
+            writeln!(output, "{}", line).unwrap();
+            output.flush().unwrap();
+        });
+        self.search = Some(Search {
+            stop,
+            ponder_hit,
+            infinite: limits.infinite,
+            handle,
+        });
+    }
+
+    /// Stops the running search, if any, and waits for its `bestmove`.
+    pub fn stop(&mut self) {
+        if let Some(search) = self.search.take() {
+            search.stop.store(true, Ordering::Relaxed);
+            search.handle.join().unwrap();
+        }
+    }
+
+    /// Waits for the running search to finish on its own. Infinite and
+    /// pondering searches are stopped instead.
+    pub fn wait(&mut self) {
+        if let Some(search) = self.search.take() {
+            let pondering = search
+                .ponder_hit
+                .as_ref()
+                .is_some_and(|ponder_hit| !ponder_hit.load(Ordering::Relaxed));
+            if search.infinite || pondering {
+                search.stop.store(true, Ordering::Relaxed);
+            }
+            search.handle.join().unwrap();
+        }
+    }
+}
+
+/// Runs a session until `quit` or the end of the input. At the end of the
+/// input, a running search is allowed to finish.
+pub fn run<R: BufRead, W: Write + Send + 'static>(input: R, output: W) {
+    let mut session = Session::new(output);
+    for line in input.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if !session.handle_line(&line) {
+            return;
+        }
+    }
+    session.wait();
+}
+
+#[test]
+fn test_parse_go() {
+    assert_eq!(
+        parse_command("go depth 5 movetime 300 nodes 1000"),
+        Ok(Command::Go(GoLimits {
+            depth: Some(5),
+            movetime: Some(Duration::from_millis(300)),
+            nodes: Some(1000),
+            infinite: false,
+            ponder: false,
+        }))
+    );
+    assert!(parse_command("go depth").is_err());
+}
+
+/// Output of a session that the test can read afterwards.
+#[cfg(test)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_session_reports_best_move() {
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let input = "position inversed moves 22-7\ngo depth 3\n";
+    run(input.as_bytes(), SharedBuffer(buffer.clone()));
+    let output = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+    assert!(output.contains("info depth 3 "));
+    assert!(output.lines().last().unwrap().starts_with("bestmove "));
+}
+
+#[test]
+fn test_session_ponders() {
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let input = "position inversed moves 22-7\ngo ponder depth 3\nponderhit\n";
+    run(input.as_bytes(), SharedBuffer(buffer.clone()));
+    let output = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+    let last = output.lines().last().unwrap();
+    assert!(last.starts_with("bestmove "));
+    assert!(last.contains(" ponder "));
+}
+
+#[test]
+fn test_session_rejects_bad_options() {
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let input = "setoption name Hash value 0\nsetoption name Threads value 0\n\
+                 setoption name MultiPV value 0\nposition inversed\ngo depth 2\n";
+    run(input.as_bytes(), SharedBuffer(buffer.clone()));
+    let output = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+    assert!(output.contains("info string error: bad size `0`"));
+    assert!(output.contains("info string error: bad thread count `0`"));
+    assert!(output.contains("info string error: bad line count `0`"));
+    assert!(output.lines().last().unwrap().starts_with("bestmove "));
+}
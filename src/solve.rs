@@ -0,0 +1,915 @@
+//! Solving the whole game by retrograde analysis.
+//!
+//! The rules do not change when the board is turned or mirrored, nor when
+//! the colours are swapped, so only one position of every class of positions
+//! equal under these symmetries is numbered. Positions with Black to move
+//! have their colours swapped, and the board is turned so that White's king
+//! stands on the lowest square of its class, then Black's king, then the
+//! pawns on the lowest squares they can be turned to. The kings then stand
+//! on one of 75 pairs of squares, neither on the centre, and the sets of
+//! squares of each side's pawns are ranked in the combinatorial number
+//! system. That makes `POSITIONS`, about 1.7 billion.
+//!
+//! Solving starts from the positions that are decided at once, where the
+//! side to move can slide its king onto the centre or has no move at all.
+//! It then walks the moves backwards, one ply of distance at a time: a
+//! position from which a move leads to a loss is a win one ply longer, and
+//! one whose moves all lead to wins is a loss one ply longer than the
+//! longest of them. Positions never reached that way are draws: neither side
+//! can force a win.
+//!
+//! Wins hold whatever the repetition rule, as winning by the shortest way
+//! never repeats a position. Draws are those of `RepetitionRule::DrawOnThreefold`:
+//! under the other rules, some of them are wins, as the side that has to
+//! avoid repeating positions may run out of moves, which the value of a
+//! position alone cannot tell.
+//!
+//! The solver keeps one byte per position, in a slice per pair of kings:
+//! the number of moves not yet known to lose while the position is
+//! undecided, and its distance once it is. Every pass over the positions
+//! goes slice by slice, split among all cores. The database keeps one byte
+//! per position too, the value with its distance, and is probed on disk
+//! without loading it.
+
+use std::{
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+    fs::File,
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::Path,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+    thread,
+};
+
+use crate::board2::{self, Board2};
+
+const fn binomials() -> [[u64; 5]; 25] {
+    let mut table = [[0; 5]; 25];
+    let mut n = 0;
+    while n < 25 {
+        table[n][0] = 1;
+        let mut k = 1;
+        while k < 5 && k <= n {
+            table[n][k] = table[n - 1][k - 1] + table[n - 1][k];
+            k += 1;
+        }
+        n += 1;
+    }
+    table
+}
+
+/// `BINOMIAL[n][k]` is n choose k.
+const BINOMIAL: [[u64; 5]; 25] = binomials();
+
+/// Sets of squares for White's pawns, once both kings are placed.
+const WHITE_PAWN_SETS: u64 = BINOMIAL[22][4];
+/// Sets of squares for Black's pawns, once White's pawns are placed too.
+const BLACK_PAWN_SETS: u64 = BINOMIAL[18][4];
+
+/// Positions per pair of kings, the slices the solver works through.
+const SLICE: u64 = WHITE_PAWN_SETS * BLACK_PAWN_SETS;
+
+const CENTRE_SQUARE: u8 = 12;
+const CENTRE: u32 = 1 << CENTRE_SQUARE;
+
+/// The square `square` goes to under symmetry number `symmetry`: bit 0
+/// reflects the board in its diagonal, bit 1 mirrors the columns and bit 2
+/// the rows.
+const fn symmetry(symmetry: usize) -> [u8; 25] {
+    let mut map = [0; 25];
+    let mut square = 0;
+    while square < 25 {
+        let (mut row, mut col) = (square / 5, square % 5);
+        if symmetry & 1 != 0 {
+            let diagonal = row;
+            row = col;
+            col = diagonal;
+        }
+        if symmetry & 2 != 0 {
+            col = 4 - col;
+        }
+        if symmetry & 4 != 0 {
+            row = 4 - row;
+        }
+        map[square] = (row * 5 + col) as u8;
+        square += 1;
+    }
+    map
+}
+
+const SYMMETRIES: [[u8; 25]; 8] = [
+    symmetry(0),
+    symmetry(1),
+    symmetry(2),
+    symmetry(3),
+    symmetry(4),
+    symmetry(5),
+    symmetry(6),
+    symmetry(7),
+];
+
+/// The lowest square `square` can be turned to.
+const fn lowest_images() -> [u8; 25] {
+    let mut lowest = [0; 25];
+    let mut square = 0;
+    while square < 25 {
+        lowest[square] = square as u8;
+        let mut g = 0;
+        while g < 8 {
+            if SYMMETRIES[g][square] < lowest[square] {
+                lowest[square] = SYMMETRIES[g][square];
+            }
+            g += 1;
+        }
+        square += 1;
+    }
+    lowest
+}
+
+const LOWEST_IMAGE: [u8; 25] = lowest_images();
+
+const KING_PAIR_COUNT: usize = 75;
+
+/// The pairs of squares of White's and Black's kings in numbered positions,
+/// and the number of each pair, `u8::MAX` for the others.
+const fn king_pairs() -> ([(u8, u8); KING_PAIR_COUNT], [[u8; 25]; 25]) {
+    let mut pairs = [(0, 0); KING_PAIR_COUNT];
+    let mut numbers = [[u8::MAX; 25]; 25];
+    let mut count = 0;
+    let mut white = 0;
+    while white < 25 {
+        if white != CENTRE_SQUARE as usize && LOWEST_IMAGE[white] == white as u8 {
+            let mut black = 0;
+            while black < 25 {
+                // Black's king is on the lowest square the symmetries that
+                // keep White's king in place can turn it to.
+                let mut lowest = true;
+                let mut g = 0;
+                while g < 8 {
+                    if SYMMETRIES[g][white] == white as u8 && SYMMETRIES[g][black] < black as u8 {
+                        lowest = false;
+                    }
+                    g += 1;
+                }
+                if lowest && black != white && black != CENTRE_SQUARE as usize {
+                    pairs[count] = (white as u8, black as u8);
+                    numbers[white][black] = count as u8;
+                    count += 1;
+                }
+                black += 1;
+            }
+        }
+        white += 1;
+    }
+    assert!(count == KING_PAIR_COUNT);
+    (pairs, numbers)
+}
+
+const KING_PAIRS: ([(u8, u8); KING_PAIR_COUNT], [[u8; 25]; 25]) = king_pairs();
+
+/// Number of numbered positions.
+pub const POSITIONS: u64 = KING_PAIR_COUNT as u64 * SLICE;
+
+/// Value of a position for the side to move, with the number of plies the
+/// game lasts with best play: the winner wins as fast as possible and the
+/// loser loses as slowly as possible.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Value {
+    Win(u32),
+    Loss(u32),
+    Draw,
+}
+
+impl Value {
+    /// Packs a win or loss in `plies` into the byte kept per position in the
+    /// database, 0 standing for a draw.
+    fn to_byte(plies: u32) -> Option<u8> {
+        u8::try_from(plies + 1).ok()
+    }
+
+    fn from_byte(byte: u8) -> Value {
+        match byte {
+            0 => Value::Draw,
+            _ if byte & 1 == 0 => Value::Win(byte as u32 - 1),
+            _ => Value::Loss(byte as u32 - 1),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Win(plies) => write!(f, "win in {} plies", plies),
+            Self::Loss(plies) => write!(f, "loss in {} plies", plies),
+            Self::Draw => write!(f, "draw"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SolveError {
+    /// The tables of the solver do not fit in memory.
+    OutOfMemory {
+        bytes: u64,
+    },
+    /// A win or loss takes more plies than the solver can record.
+    TooLong,
+    Io(io::Error),
+    /// The file is not a database written by `Database::solve`.
+    InvalidDatabase,
+}
+
+impl Display for SolveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfMemory { bytes } => {
+                write!(f, "cannot allocate {} MB", bytes.div_ceil(1_000_000))
+            }
+            Self::TooLong => write!(f, "a game lasts longer than the solver can record"),
+            Self::Io(e) => write!(f, "{}", e),
+            Self::InvalidDatabase => write!(f, "not a solved database"),
+        }
+    }
+}
+
+impl std::error::Error for SolveError {}
+
+/// A position with White to move, the pawns of each side as a bit array
+/// like `Board2::flatten_to_bitarray`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Position {
+    white_king: u8,
+    black_king: u8,
+    white_pawns: u32,
+    black_pawns: u32,
+}
+
+fn squares(pieces: u32) -> impl Iterator<Item = u8> {
+    let mut pieces = pieces;
+    std::iter::from_fn(move || {
+        let square = pieces.trailing_zeros();
+        pieces &= pieces.wrapping_sub(1);
+        (square < 32).then_some(square as u8)
+    })
+}
+
+fn transformed(pieces: u32, map: &[u8; 25]) -> u32 {
+    squares(pieces).fold(0, |pieces, square| pieces | 1 << map[square as usize])
+}
+
+impl Position {
+    /// `board`, with the colours swapped if Black is to move.
+    fn from_board(board: Board2) -> Position {
+        let pieces = |pawns: [u8; 4]| pawns.iter().fold(0, |pieces, &square| pieces | 1 << square);
+        let (white_pawns, black_pawns) = (pieces(board.white_pawns()), pieces(board.black_pawns()));
+        if board.maximizing() {
+            Position {
+                white_king: board.white_king(),
+                black_king: board.black_king(),
+                white_pawns,
+                black_pawns,
+            }
+        } else {
+            Position {
+                white_king: board.black_king(),
+                black_king: board.white_king(),
+                white_pawns: black_pawns,
+                black_pawns: white_pawns,
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn board(self) -> Board2 {
+        let mut pos = [0; 11];
+        for (i, square) in squares(self.white_pawns).enumerate() {
+            pos[i] = square;
+        }
+        for (i, square) in squares(self.black_pawns).enumerate() {
+            pos[4 + i] = square;
+        }
+        pos[8] = self.white_king;
+        pos[9] = self.black_king;
+        pos[10] = 1;
+        Board2::try_from_positions(&pos).unwrap()
+    }
+
+    fn occupied(self) -> u32 {
+        self.white_pawns | self.black_pawns | 1 << self.white_king | 1 << self.black_king
+    }
+
+    /// The same position with the other side to move.
+    fn swapped(self) -> Position {
+        Position {
+            white_king: self.black_king,
+            black_king: self.white_king,
+            white_pawns: self.black_pawns,
+            black_pawns: self.white_pawns,
+        }
+    }
+
+    /// The numbered position of the class of this one, see the module
+    /// documentation.
+    fn canonical(self) -> Position {
+        let lowest = LOWEST_IMAGE[self.white_king as usize];
+        SYMMETRIES
+            .iter()
+            .filter(|map| map[self.white_king as usize] == lowest)
+            .map(|map| Position {
+                white_king: lowest,
+                black_king: map[self.black_king as usize],
+                white_pawns: transformed(self.white_pawns, map),
+                black_pawns: transformed(self.black_pawns, map),
+            })
+            .min()
+            .unwrap()
+    }
+
+    /// Index of this position, which must be canonical, in `0..POSITIONS`.
+    fn index(self) -> u64 {
+        let pair = KING_PAIRS.1[self.white_king as usize][self.black_king as usize];
+        debug_assert!(pair != u8::MAX);
+        let kings = CENTRE | 1 << self.white_king | 1 << self.black_king;
+        (pair as u64 * WHITE_PAWN_SETS + rank_set(self.white_pawns, kings)) * BLACK_PAWN_SETS
+            + rank_set(self.black_pawns, kings | self.white_pawns)
+    }
+
+    fn from_index(index: u64) -> Position {
+        let (white_king, black_king) = KING_PAIRS.0[(index / SLICE) as usize];
+        let kings = CENTRE | 1 << white_king | 1 << black_king;
+        let white_pawns = unrank_set(index / BLACK_PAWN_SETS % WHITE_PAWN_SETS, kings);
+        let black_pawns = unrank_set(index % BLACK_PAWN_SETS, kings | white_pawns);
+        Position {
+            white_king,
+            black_king,
+            white_pawns,
+            black_pawns,
+        }
+    }
+}
+
+/// Index of the class of `board`, which has not ended.
+fn index(board: Board2) -> u64 {
+    Position::from_board(board).canonical().index()
+}
+
+/// Rank of the set of four squares `pieces` among the sets of four squares
+/// not in `taken`.
+fn rank_set(pieces: u32, taken: u32) -> u64 {
+    squares(pieces)
+        .enumerate()
+        .map(|(i, square)| {
+            let compressed = square as u32 - (taken & ((1 << square) - 1)).count_ones();
+            BINOMIAL[compressed as usize][i + 1]
+        })
+        .sum()
+}
+
+fn unrank_set(mut rank: u64, taken: u32) -> u32 {
+    let mut pieces = 0;
+    let mut compressed = 25;
+    for i in (1..5).rev() {
+        compressed -= 1;
+        while BINOMIAL[compressed][i] > rank {
+            compressed -= 1;
+        }
+        rank -= BINOMIAL[compressed][i];
+        // The `compressed`-th square not in `taken`.
+        let mut free = !taken & ((1 << 25) - 1);
+        for _ in 0..compressed {
+            free &= free - 1;
+        }
+        pieces |= 1 << free.trailing_zeros();
+    }
+    pieces
+}
+
+const DIRECTIONS: [(i8, i8); 8] = [
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, -1),
+    (0, 1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+];
+
+/// The square one step from `square` in `direction`, if on the board.
+fn step(square: u8, (row, col): (i8, i8)) -> Option<u8> {
+    let (r, c) = ((square / 5) as i8 + row, (square % 5) as i8 + col);
+    ((0..5).contains(&r) && (0..5).contains(&c)).then_some((r * 5 + c) as u8)
+}
+
+/// Calls `f` with the positions after every move of `position`, with the
+/// colours swapped so that White is to move, or returns `None` if one of
+/// them wins at once.
+fn successors(position: Position, mut f: impl FnMut(Position)) -> Option<()> {
+    let occupied = position.occupied();
+    let king_moves = board2::destinations(occupied, position.white_king);
+    if king_moves & CENTRE != 0 {
+        return None;
+    }
+    for from in squares(position.white_pawns) {
+        for to in squares(board2::destinations(occupied, from) & !CENTRE) {
+            f(Position {
+                white_pawns: position.white_pawns ^ (1 << from | 1 << to),
+                ..position
+            }
+            .swapped());
+        }
+    }
+    for to in squares(king_moves) {
+        f(Position {
+            white_king: to,
+            ..position
+        }
+        .swapped());
+    }
+    Some(())
+}
+
+/// Calls `f` with the positions with a move to `position`, which has not
+/// ended: Black takes back a move of one of its pieces, and the colours are
+/// swapped so that White is to move.
+fn predecessors(position: Position, mut f: impl FnMut(Position)) {
+    let occupied = position.occupied();
+    let pieces = squares(position.black_pawns).chain([position.black_king]);
+    for to in pieces {
+        let is_king = to == position.black_king;
+        for &(row, col) in &DIRECTIONS {
+            // The piece stopped on `to`, so the next square is taken.
+            if step(to, (row, col)).is_some_and(|next| occupied & 1 << next == 0) {
+                continue;
+            }
+            let mut from = to;
+            while let Some(back) = step(from, (-row, -col)) {
+                if occupied & 1 << back != 0 {
+                    break;
+                }
+                from = back;
+                // Pawns cannot stop on the centre, and a king there would
+                // have ended the game.
+                if from == CENTRE_SQUARE {
+                    continue;
+                }
+                let previous = if is_king {
+                    Position {
+                        black_king: from,
+                        ..position
+                    }
+                } else {
+                    Position {
+                        black_pawns: position.black_pawns ^ (1 << from | 1 << to),
+                        ..position
+                    }
+                };
+                f(previous.swapped());
+            }
+        }
+    }
+}
+
+/// At most five pieces with eight directions each.
+const MAX_MOVES: usize = 40;
+/// Moves taken back, up to four squares in each of those directions.
+const MAX_PREDECESSORS: usize = 4 * MAX_MOVES;
+
+/// Calls `f` once per distinct index in `indices`.
+fn for_each_distinct(indices: &mut [u64], f: &mut dyn FnMut(u64)) {
+    indices.sort_unstable();
+    for (i, &index) in indices.iter().enumerate() {
+        if i == 0 || indices[i - 1] != index {
+            f(index);
+        }
+    }
+}
+
+/// What the solver learns from a position before looking at its moves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Moves {
+    /// The side to move wins with its next move.
+    WinsAtOnce,
+    /// Number of distinct positions the moves lead to.
+    Count(usize),
+    /// The index is not used by any position.
+    Unused,
+}
+
+/// A game as the solver sees it: positions numbered `0..len()` in slices of
+/// `slice_len()`, the moves out of them, and the moves into them. Moves
+/// leading to the same position count once.
+trait Space: Sync {
+    fn len(&self) -> u64;
+    fn slice_len(&self) -> u64;
+    fn moves(&self, index: u64) -> Moves;
+    fn predecessors(&self, index: u64, f: &mut dyn FnMut(u64));
+}
+
+/// The game itself, numbered by `Position::index`.
+struct Positions;
+
+impl Space for Positions {
+    fn len(&self) -> u64 {
+        POSITIONS
+    }
+
+    fn slice_len(&self) -> u64 {
+        SLICE
+    }
+
+    fn moves(&self, index: u64) -> Moves {
+        let position = Position::from_index(index);
+        // The twin of a numbered position when the symmetries keeping both
+        // kings in place turn the pawns to lower squares.
+        if position.canonical() != position {
+            return Moves::Unused;
+        }
+        let mut indices = [0; MAX_MOVES];
+        let mut len = 0;
+        let wins_at_once = successors(position, |next| {
+            indices[len] = next.canonical().index();
+            len += 1;
+        })
+        .is_none();
+        if wins_at_once {
+            return Moves::WinsAtOnce;
+        }
+        let mut count = 0;
+        for_each_distinct(&mut indices[..len], &mut |_| count += 1);
+        Moves::Count(count)
+    }
+
+    fn predecessors(&self, index: u64, f: &mut dyn FnMut(u64)) {
+        let mut indices = [0; MAX_PREDECESSORS];
+        let mut len = 0;
+        predecessors(Position::from_index(index), |previous| {
+            indices[len] = previous.canonical().index();
+            len += 1;
+        });
+        for_each_distinct(&mut indices[..len], f);
+    }
+}
+
+/// The byte the solver keeps for a position: up to `MAX_MOVES`, the number
+/// of moves not known to lead to a win for the other side while it is
+/// undecided, 0 for unused indices; from `DECIDED`, the distance of a win
+/// or loss added to it.
+const DECIDED: u8 = MAX_MOVES as u8 + 1;
+
+/// The byte of the database for the byte of the solver.
+fn database_byte(state: u8) -> u8 {
+    if state < DECIDED {
+        0
+    } else {
+        Value::to_byte((state - DECIDED) as u32).unwrap()
+    }
+}
+
+/// Allocates `len` zeroed bytes, or fails rather than aborting.
+fn table(len: u64) -> Option<Box<[AtomicU8]>> {
+    let len = usize::try_from(len).ok()?;
+    let mut table = Vec::new();
+    table.try_reserve_exact(len).ok()?;
+    table.extend((0..len).map(|_| AtomicU8::new(0)));
+    Some(table.into_boxed_slice())
+}
+
+/// Positions per unit of work of a thread.
+const CHUNK: u64 = 1 << 16;
+
+/// Calls `work` on ranges of the indices in `0..len` on all cores, slice by
+/// slice, and returns the sum of what it returned.
+fn in_parallel(len: u64, slice_len: u64, work: impl Fn(Range<u64>) -> u64 + Sync) -> u64 {
+    let chunks_per_slice = slice_len.div_ceil(CHUNK);
+    let chunks = len / slice_len * chunks_per_slice;
+    let next = AtomicU64::new(0);
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    thread::scope(|scope| {
+        let workers = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut total = 0;
+                    loop {
+                        let chunk = next.fetch_add(1, Ordering::Relaxed);
+                        if chunk >= chunks {
+                            return total;
+                        }
+                        let slice_start = chunk / chunks_per_slice * slice_len;
+                        let start = slice_start + chunk % chunks_per_slice * CHUNK;
+                        let end = (start + CHUNK).min(slice_start + slice_len);
+                        total += work(start..end);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        workers.into_iter().map(|w| w.join().unwrap()).sum()
+    })
+}
+
+/// Solves `space`, returning the byte of the solver for every position, a
+/// table per slice. `progress` is called with every distance once all
+/// positions decided within it are known, and how many they are.
+fn retrograde(
+    space: &impl Space,
+    mut progress: impl FnMut(u32, u64),
+) -> Result<Vec<Box<[AtomicU8]>>, SolveError> {
+    let (len, slice_len) = (space.len(), space.slice_len());
+    let tables = (0..len / slice_len)
+        .map(|_| table(slice_len).ok_or(SolveError::OutOfMemory { bytes: len }))
+        .collect::<Result<Vec<_>, _>>()?;
+    let state = |index: u64| &tables[(index / slice_len) as usize][(index % slice_len) as usize];
+    in_parallel(len, slice_len, |indices| {
+        for index in indices {
+            let byte = match space.moves(index) {
+                Moves::WinsAtOnce => DECIDED + 1,
+                Moves::Count(0) => DECIDED,
+                Moves::Count(n) => n as u8,
+                Moves::Unused => 0,
+            };
+            state(index).store(byte, Ordering::Relaxed);
+        }
+        0
+    });
+    let too_long = AtomicBool::new(false);
+    let mut plies = 0;
+    loop {
+        let byte = match u8::try_from(DECIDED as u32 + plies) {
+            Ok(byte) => byte,
+            Err(_) => return Ok(tables),
+        };
+        let next = u8::try_from(DECIDED as u32 + plies + 1).ok();
+        let found = in_parallel(len, slice_len, |indices| {
+            let mut found = 0;
+            for index in indices {
+                if state(index).load(Ordering::Relaxed) != byte {
+                    continue;
+                }
+                found += 1;
+                let next = match next {
+                    Some(next) => next,
+                    None => {
+                        too_long.store(true, Ordering::Relaxed);
+                        continue;
+                    }
+                };
+                space.predecessors(index, &mut |previous| {
+                    // After a loss, the move wins; after a win, the position
+                    // is lost once no move is left that might not lose.
+                    let _ =
+                        state(previous).fetch_update(Ordering::Relaxed, Ordering::Relaxed, |s| {
+                            if !(1..DECIDED).contains(&s) {
+                                None
+                            } else if plies % 2 == 0 || s == 1 {
+                                Some(next)
+                            } else {
+                                Some(s - 1)
+                            }
+                        });
+                });
+            }
+            found
+        });
+        if too_long.load(Ordering::Relaxed) {
+            return Err(SolveError::TooLong);
+        }
+        progress(plies, found);
+        // Wins in one ply are found without a previous distance.
+        if found == 0 && plies > 0 {
+            return Ok(tables);
+        }
+        plies += 1;
+    }
+}
+
+/// The solved values of every position, in a file.
+pub struct Database {
+    file: File,
+}
+
+const MAGIC: &[u8; 8] = b"PNGNSLV2";
+
+impl Database {
+    /// Solves the game, see the module documentation, and saves the
+    /// database to `path`. `progress` is called after every distance with
+    /// how many positions are decided in exactly that many plies.
+    pub fn solve(
+        path: impl AsRef<Path>,
+        progress: impl FnMut(u32, u64),
+    ) -> Result<Database, SolveError> {
+        let tables = retrograde(&Positions, progress)?;
+        let mut file = BufWriter::new(File::create(&path).map_err(SolveError::Io)?);
+        file.write_all(MAGIC).map_err(SolveError::Io)?;
+        let mut bytes = vec![0; SLICE as usize];
+        for table in tables {
+            for (byte, state) in bytes.iter_mut().zip(table.iter()) {
+                *byte = database_byte(state.load(Ordering::Relaxed));
+            }
+            file.write_all(&bytes).map_err(SolveError::Io)?;
+        }
+        file.flush().map_err(SolveError::Io)?;
+        Database::open(path)
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Database, SolveError> {
+        let mut file = File::open(path).map_err(SolveError::Io)?;
+        let mut magic = [0; 8];
+        file.read_exact(&mut magic).map_err(SolveError::Io)?;
+        let len = file.metadata().map_err(SolveError::Io)?.len();
+        if &magic != MAGIC || len != MAGIC.len() as u64 + POSITIONS {
+            return Err(SolveError::InvalidDatabase);
+        }
+        Ok(Database { file })
+    }
+
+    /// The value of `board` for the side to move, or `None` if the game
+    /// already ended.
+    pub fn probe(&mut self, board: Board2) -> Result<Option<Value>, SolveError> {
+        if board.ended() {
+            return Ok(None);
+        }
+        let offset = MAGIC.len() as u64 + index(board);
+        let file = &mut self.file;
+        file.seek(SeekFrom::Start(offset)).map_err(SolveError::Io)?;
+        let mut byte = [0];
+        file.read_exact(&mut byte).map_err(SolveError::Io)?;
+        Ok(Some(Value::from_byte(byte[0])))
+    }
+}
+
+#[test]
+fn test_index() {
+    use crate::board::BoardState;
+
+    assert_eq!(POSITIONS, 1_678_792_500);
+    for index in [0, 1, 12345, SLICE, POSITIONS - 1] {
+        assert_eq!(Position::from_index(index).index(), index);
+    }
+    for _ in 0..1000 {
+        let (state, player) = BoardState::random();
+        let board = state.to_board2(player);
+        if board.ended() {
+            continue;
+        }
+        let position = Position::from_board(board).canonical();
+        let index = position.index();
+        assert!(index < POSITIONS);
+        assert_eq!(Position::from_index(index), position);
+        assert_eq!(Position::from_board(position.board()), position);
+        // Turned, mirrored and with the colours swapped, it is the same.
+        assert_eq!(self::index(board.reflect()), index);
+        let mut swapped = [0; 11];
+        swapped[..4].copy_from_slice(&board.black_pawns());
+        swapped[4..8].copy_from_slice(&board.white_pawns());
+        swapped[8] = board.black_king();
+        swapped[9] = board.white_king();
+        swapped[10] = if board.maximizing() { 0 } else { 1 };
+        let swapped = Board2::try_from_positions(&swapped).unwrap();
+        assert_eq!(self::index(swapped), index);
+        for map in &SYMMETRIES {
+            let turned = Position {
+                white_king: map[position.white_king as usize],
+                black_king: map[position.black_king as usize],
+                white_pawns: transformed(position.white_pawns, map),
+                black_pawns: transformed(position.black_pawns, map),
+            };
+            assert_eq!(turned.canonical(), position);
+        }
+    }
+}
+
+#[test]
+fn test_moves() {
+    use crate::board::BoardState;
+
+    for _ in 0..300 {
+        let (state, player) = BoardState::random();
+        let board = state.to_board2(player);
+        if board.ended() {
+            continue;
+        }
+        let index = self::index(board);
+        let mut moves = board
+            .legal_moves()
+            .iter()
+            .map(|&(_, next)| (!next.ended()).then(|| self::index(next)))
+            .collect::<Option<Vec<_>>>();
+        match Positions.moves(index) {
+            Moves::WinsAtOnce => assert_eq!(moves, None),
+            Moves::Count(n) => {
+                let moves = moves.as_mut().unwrap();
+                moves.sort_unstable();
+                moves.dedup();
+                assert_eq!(moves.len(), n);
+                // Every position a move leads to is found back as one with a
+                // move to the board.
+                for &next in moves.iter() {
+                    let mut found = false;
+                    Positions.predecessors(next, &mut |previous| found |= previous == index);
+                    assert!(found);
+                }
+            }
+            Moves::Unused => panic!("{:?} is not numbered", board),
+        }
+        // And every position found has a move to the board.
+        Positions.predecessors(index, &mut |previous| {
+            let previous = Position::from_index(previous).board();
+            assert!(previous
+                .legal_moves()
+                .iter()
+                .any(|&(_, next)| !next.ended() && self::index(next) == index));
+        });
+    }
+}
+
+#[test]
+fn test_retrograde() {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// A random game on a few positions, with cycles.
+    struct Graph {
+        wins_at_once: Vec<bool>,
+        edges: Vec<Vec<u64>>,
+    }
+
+    impl Space for Graph {
+        fn len(&self) -> u64 {
+            self.edges.len() as u64
+        }
+
+        fn slice_len(&self) -> u64 {
+            100
+        }
+
+        fn moves(&self, index: u64) -> Moves {
+            if self.wins_at_once[index as usize] {
+                Moves::WinsAtOnce
+            } else {
+                Moves::Count(self.edges[index as usize].len())
+            }
+        }
+
+        fn predecessors(&self, index: u64, f: &mut dyn FnMut(u64)) {
+            for (previous, edges) in self.edges.iter().enumerate() {
+                if edges.contains(&index) {
+                    f(previous as u64);
+                }
+            }
+        }
+    }
+
+    let mut rng = StdRng::seed_from_u64(1);
+    for _ in 0..20 {
+        let len = 300;
+        let graph = Graph {
+            wins_at_once: (0..len).map(|_| rng.gen_bool(0.05)).collect(),
+            edges: (0..len)
+                .map(|_| {
+                    let mut edges = (0..rng.gen_range(0..4))
+                        .map(|_| rng.gen_range(0..len as u64))
+                        .collect::<Vec<_>>();
+                    edges.sort_unstable();
+                    edges.dedup();
+                    edges
+                })
+                .collect(),
+        };
+        let tables = retrograde(&graph, |_, _| {}).unwrap();
+        let value = |index: u64| {
+            let state = tables[index as usize / 100][index as usize % 100].load(Ordering::Relaxed);
+            Value::from_byte(database_byte(state))
+        };
+        for index in 0..len as u64 {
+            let children = graph.edges[index as usize]
+                .iter()
+                .map(|&child| value(child))
+                .collect::<Vec<_>>();
+            let fastest_win = children
+                .iter()
+                .filter_map(|child| match child {
+                    Value::Loss(plies) => Some(plies + 1),
+                    _ => None,
+                })
+                .min();
+            let slowest_loss = children
+                .iter()
+                .map(|child| match child {
+                    Value::Win(plies) => Some(plies + 1),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()
+                .map(|plies| plies.into_iter().max().unwrap_or(0));
+            let expected = if graph.wins_at_once[index as usize] {
+                Value::Win(1)
+            } else if let Some(plies) = fastest_win {
+                Value::Win(plies)
+            } else if let Some(plies) = slowest_loss {
+                Value::Loss(plies)
+            } else {
+                Value::Draw
+            };
+            assert_eq!(value(index), expected);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! A fixed-size transposition table.
+//!
+//! The table is a flat array of entries indexed by a hash of the board, so
+//! its memory use is set once when it is created. Each slot holds a single
+//! entry; on a collision the new entry wins if the old one is from an earlier
+//! search, or was searched to no greater depth.
+//!
+//! The table can be shared between threads without locking. An entry is
+//! packed into one 64-bit word, stored next to the board XORed with that
+//! word; a read whose two words do not belong together, because another
+//! thread wrote the slot in between, then simply does not match the board.
+
+use std::{
+    mem,
+    sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering},
+};
+
+use crate::board2::{Board2, Move};
+
+/// How the stored score relates to the true minimax value of the position.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    /// The score is the value of the position.
+    Exact,
+    /// The search failed high: the value is at least the score.
+    Lower,
+    /// The search failed low: the value is at most the score.
+    Upper,
+}
+
+impl Bound {
+    /// The bound on the negated score.
+    pub fn negated(self) -> Bound {
+        match self {
+            Bound::Exact => Bound::Exact,
+            Bound::Lower => Bound::Upper,
+            Bound::Upper => Bound::Lower,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub score: i32,
+    pub best_move: Move,
+    /// Number of plies searched below the position.
+    pub depth: u8,
+    pub bound: Bound,
+    age: u8,
+}
+
+const OCCUPIED: u64 = 1 << 63;
+const AGE_MASK: u8 = 0b11111;
+
+impl Entry {
+    /// The entry from the other side's point of view.
+    pub fn negated(self) -> Entry {
+        Entry {
+            score: -self.score,
+            bound: self.bound.negated(),
+            ..self
+        }
+    }
+
+    /// The score to return without searching, if the entry was searched at
+    /// least `depth` plies deep and its bound allows a cutoff in the window
+    /// `alpha..beta`.
+    pub fn cutoff(&self, depth: usize, alpha: i32, beta: i32) -> Option<i32> {
+        if (self.depth as usize) < depth {
+            return None;
+        }
+        match self.bound {
+            Bound::Exact => Some(self.score),
+            Bound::Lower if self.score >= beta => Some(self.score),
+            Bound::Upper if self.score <= alpha => Some(self.score),
+            _ => None,
+        }
+    }
+
+    // Bits 0-31 hold the score, 32-47 the move, 48-55 the depth, 56-57 the
+    // bound, 58-62 the age and 63 marks the slot as occupied.
+    fn pack(&self) -> u64 {
+        let m = &self.best_move;
+        let packed_move = (m.from_offset() as u64) | (m.from() as u64) << 6 | (m.to() as u64) << 11;
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        (self.score as u32 as u64)
+            | packed_move << 32
+            | (self.depth as u64) << 48
+            | bound << 56
+            | ((self.age & AGE_MASK) as u64) << 58
+            | OCCUPIED
+    }
+
+    fn unpack(data: u64) -> Entry {
+        let packed_move = (data >> 32) as u16;
+        Entry {
+            score: data as u32 as i32,
+            best_move: Move::new(
+                (packed_move & 0b111111) as u8,
+                (packed_move >> 6 & 0b11111) as u8,
+                (packed_move >> 11 & 0b11111) as u8,
+            ),
+            depth: (data >> 48) as u8,
+            bound: match data >> 56 & 0b11 {
+                0 => Bound::Exact,
+                1 => Bound::Lower,
+                _ => Bound::Upper,
+            },
+            age: (data >> 58) as u8 & AGE_MASK,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Slot {
+    /// The board XORed with `data`.
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+pub struct TranspositionTable {
+    slots: Vec<Slot>,
+    age: AtomicU8,
+    used: AtomicUsize,
+}
+
+impl TranspositionTable {
+    /// The largest size in megabytes whose byte count fits in a `usize`.
+    pub const MAX_SIZE_MB: usize = usize::MAX >> 20;
+
+    /// Creates a table using about `size_mb` megabytes, with at least one slot.
+    /// Sizes above `MAX_SIZE_MB` are treated as `MAX_SIZE_MB`.
+    pub fn new(size_mb: usize) -> TranspositionTable {
+        let len = (size_mb.min(Self::MAX_SIZE_MB) << 20) / mem::size_of::<Slot>();
+        let len = len.max(1);
+        TranspositionTable {
+            slots: (0..len).map(|_| Slot::default()).collect(),
+            age: AtomicU8::new(0),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    fn slot(&self, board: Board2) -> &Slot {
+        // Fibonacci hashing spreads the packed board over the whole table.
+        let hash = board.data().wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        &self.slots[((hash as u128 * self.slots.len() as u128) >> 64) as usize]
+    }
+
+    pub fn get(&self, board: Board2) -> Option<Entry> {
+        let slot = self.slot(board);
+        let data = slot.data.load(Ordering::Relaxed);
+        let key = slot.key.load(Ordering::Relaxed);
+        if data & OCCUPIED != 0 && key ^ data == board.data() {
+            Some(Entry::unpack(data))
+        } else {
+            None
+        }
+    }
+
+    pub fn insert(&self, board: Board2, score: i32, best_move: Move, depth: usize, bound: Bound) {
+        let slot = self.slot(board);
+        let old_data = slot.data.load(Ordering::Relaxed);
+        let age = self.age.load(Ordering::Relaxed);
+        let replace = if old_data & OCCUPIED == 0 {
+            self.used.fetch_add(1, Ordering::Relaxed);
+            true
+        } else {
+            let old = Entry::unpack(old_data);
+            slot.key.load(Ordering::Relaxed) ^ old_data == board.data()
+                || old.age != age
+                || depth >= old.depth as usize
+        };
+        if replace {
+            let data = Entry {
+                score,
+                best_move,
+                depth: depth.min(u8::MAX as usize) as u8,
+                bound,
+                age,
+            }
+            .pack();
+            slot.data.store(data, Ordering::Relaxed);
+            slot.key.store(board.data() ^ data, Ordering::Relaxed);
+        }
+    }
+
+    /// Marks every stored entry as coming from an earlier search, so that new
+    /// entries replace them regardless of depth.
+    pub fn new_search(&self) {
+        let age = self.age.load(Ordering::Relaxed);
+        self.age
+            .store(age.wrapping_add(1) & AGE_MASK, Ordering::Relaxed);
+    }
+
+    /// Empties the table. Like every other method, it may be called while
+    /// other threads use the table; their entries then survive or not.
+    pub fn clear(&self) {
+        for slot in self.slots.iter() {
+            slot.data.store(0, Ordering::Relaxed);
+            slot.key.store(0, Ordering::Relaxed);
+        }
+        self.used.store(0, Ordering::Relaxed);
+    }
+
+    /// Number of occupied slots.
+    pub fn len(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+}
+
+#[test]
+fn test_replacement() {
+    let tt = TranspositionTable::new(0);
+    assert_eq!(tt.capacity(), 1);
+    let board = Board2::new_original();
+    let (m, next) = board.legal_moves()[0];
+    tt.insert(board, -5, m, 3, Bound::Exact);
+    assert_eq!(tt.get(board).unwrap().best_move, m);
+    assert_eq!(tt.get(board).unwrap().cutoff(3, -10, 0), Some(-5));
+    assert_eq!(tt.get(board).unwrap().cutoff(4, -10, 0), None);
+    assert!(tt.get(next).is_none());
+
+    // A shallower entry does not replace a deeper one from the same search...
+    tt.insert(next, 7, m, 2, Bound::Lower);
+    assert!(tt.get(next).is_none());
+    assert_eq!(tt.get(board).unwrap().depth, 3);
+    // ...but does replace one from an earlier search.
+    tt.new_search();
+    tt.insert(next, 7, m, 2, Bound::Lower);
+    let entry = tt.get(next).unwrap();
+    assert_eq!(entry.cutoff(1, 0, 7), Some(7));
+    assert_eq!(entry.cutoff(1, 0, 8), None);
+    assert_eq!(tt.len(), 1);
+
+    tt.clear();
+    assert!(tt.get(next).is_none());
+    assert!(tt.is_empty());
+}
+
+#[test]
+fn test_entry_packing() {
+    let board = Board2::new_with_king_inversed();
+    for (m, _) in board.legal_moves() {
+        for (score, bound) in [
+            (-100000, Bound::Upper),
+            (0, Bound::Exact),
+            (12345, Bound::Lower),
+        ] {
+            let entry = Entry {
+                score,
+                best_move: m,
+                depth: 200,
+                bound,
+                age: 31,
+            };
+            assert_eq!(Entry::unpack(entry.pack()), entry);
+        }
+    }
+}
@@ -0,0 +1,311 @@
+//! Texel-style tuning of the evaluation weights.
+//!
+//! Positions of finished games are labelled with the result of their game,
+//! and the weights of a `TableEvaluator` are fitted so that a logistic
+//! function of the evaluation predicts those results as well as possible:
+//! a local search changes one weight at a time, keeping every change that
+//! lowers the mean squared error of the predictions.
+//!
+//! Table weights are tuned per class of squares that are equivalent under
+//! the symmetries of the board, mirroring and swapping the sides, so that
+//! the evaluation stays fair to both colours; the mobility terms are tuned
+//! as they are. The games usually come from
+//! `self_play`, played at a fixed depth from positions randomised by a few
+//! random moves.
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{
+    board2::Board2,
+    eval::{Evaluator, TableEvaluator, MAX_EVALUATION},
+    game::{Game, GameResult, RepetitionRule},
+    SearchLimits, SearchOptions, Searcher,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct SelfPlayOptions {
+    pub start: Board2,
+    pub repetition_rule: RepetitionRule,
+    /// Depth of the search for every move.
+    pub depth: usize,
+    /// Random moves played from `start` before the engine takes over.
+    pub random_plies: usize,
+    /// Games still going after this many plies are abandoned.
+    pub max_plies: usize,
+}
+
+impl Default for SelfPlayOptions {
+    fn default() -> SelfPlayOptions {
+        SelfPlayOptions {
+            start: Board2::new_original(),
+            repetition_rule: RepetitionRule::default(),
+            depth: 4,
+            random_plies: 4,
+            max_plies: 200,
+        }
+    }
+}
+
+/// Plays one game of the engine against itself, evaluating with
+/// `evaluator`.
+pub fn self_play(
+    options: &SelfPlayOptions,
+    evaluator: &TableEvaluator,
+    rng: &mut impl Rng,
+) -> Game {
+    let mut game = Game::with_repetition_rule(options.start, options.repetition_rule);
+    game.metadata.white = "penguin".to_string();
+    game.metadata.black = "penguin".to_string();
+    let search_options = SearchOptions {
+        repetition_rule: options.repetition_rule,
+        tt_size_mb: 4,
+        ..SearchOptions::default()
+    };
+    let mut searcher = Searcher::with_evaluator(search_options, evaluator.clone());
+    let limits = SearchLimits {
+        max_depth: Some(options.depth),
+        ..SearchLimits::default()
+    };
+    while game.result() == GameResult::Ongoing && game.moves().len() < options.max_plies {
+        let m = if game.moves().len() < options.random_plies {
+            game.legal_moves().choose(rng).map(|&(m, _)| m)
+        } else {
+            let best_move = searcher.find_best_move(
+                game.current(),
+                || false,
+                |_| {},
+                limits,
+                game.positions().to_vec(),
+            );
+            best_move.or_else(|| game.legal_moves().first().map(|&(m, _)| m))
+        };
+        match m {
+            Some(m) => game.play(m).unwrap(),
+            // Only a start without moves has no result yet: the side to move
+            // loses.
+            None => {
+                game.set_result(if game.current().maximizing() {
+                    GameResult::BlackWins
+                } else {
+                    GameResult::WhiteWins
+                });
+                break;
+            }
+        }
+    }
+    game
+}
+
+/// A position labelled with the result of its game: 1 if White won, 0 if
+/// Black won and 0.5 for a draw.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    pub board: Board2,
+    pub result: f64,
+}
+
+/// The positions of the finished `games`, except the final ones, which the
+/// search scores without the evaluation. Unfinished games are left out.
+pub fn samples(games: &[Game]) -> Vec<Sample> {
+    let mut samples = vec![];
+    for game in games {
+        let result = match game.result() {
+            GameResult::WhiteWins => 1.0,
+            GameResult::BlackWins => 0.0,
+            GameResult::Draw => 0.5,
+            GameResult::Ongoing => continue,
+        };
+        samples.extend(
+            game.positions()
+                .iter()
+                .filter(|board| !board.ended())
+                .map(|&board| Sample { board, result }),
+        );
+    }
+    samples
+}
+
+/// Expected result for White of a position evaluated `score`, on the same
+/// scale as `Sample::result`.
+fn predict(score: i32, scale: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-scale * score as f64 / 400.0))
+}
+
+/// Mean squared error of the predictions of `evaluator` for `samples`.
+pub fn error(samples: &[Sample], evaluator: &impl Evaluator, scale: f64) -> f64 {
+    let total: f64 = samples
+        .iter()
+        .map(|sample| {
+            let error = sample.result - predict(evaluator.evaluate(sample.board), scale);
+            error * error
+        })
+        .sum();
+    total / samples.len().max(1) as f64
+}
+
+/// The scale of the logistic function that fits `samples` best for the
+/// current weights, so that tuning changes the weights rather than their
+/// overall size.
+pub fn fit_scale(samples: &[Sample], evaluator: &impl Evaluator) -> f64 {
+    let mut scale = 1.0;
+    let mut best_error = error(samples, evaluator, scale);
+    let mut step = 0.5;
+    while step > 1e-4 {
+        let better = [scale + step, scale - step]
+            .iter()
+            .copied()
+            .filter(|&candidate| candidate > 0.0)
+            .map(|candidate| (candidate, error(samples, evaluator, candidate)))
+            .find(|&(_, candidate_error)| candidate_error < best_error);
+        match better {
+            Some((candidate, candidate_error)) => {
+                scale = candidate;
+                best_error = candidate_error;
+            }
+            None => step /= 2.0,
+        }
+    }
+    scale
+}
+
+/// Index of the class of `square` under mirroring and swapping the sides;
+/// the centre is class 8.
+fn square_class(square: usize) -> usize {
+    let (row, col) = (square / 5, square % 5);
+    row.min(4 - row) * 3 + col.min(4 - col)
+}
+
+const CENTRE_CLASS: usize = 8;
+
+/// Passes over the weights per step size; a small sample can otherwise keep
+/// improving by pushing the weights apart for a long time.
+const MAX_PASSES: usize = 50;
+
+/// A weight, or a class of table weights, changed as one by the tuning.
+#[derive(Clone, Copy, Debug)]
+enum Weight {
+    Pawn(usize),
+    King(usize),
+    KingMoves,
+    KingRay,
+    Stopper,
+    Mobility,
+}
+
+fn tuned_weights() -> Vec<Weight> {
+    let mut weights = vec![];
+    weights.extend((0..CENTRE_CLASS).map(Weight::Pawn));
+    weights.extend((0..CENTRE_CLASS).map(Weight::King));
+    weights.extend([
+        Weight::KingMoves,
+        Weight::KingRay,
+        Weight::Stopper,
+        Weight::Mobility,
+    ]);
+    weights
+}
+
+/// Adds `delta` to `weight`, unless that lets the evaluation exceed
+/// `MAX_EVALUATION`, however few samples there are.
+fn adjusted(weights: &TableEvaluator, weight: Weight, delta: i32) -> Option<TableEvaluator> {
+    let mut weights = weights.clone();
+    match weight {
+        Weight::Pawn(class) => adjust_class(&mut weights.pawn, class, delta),
+        Weight::King(class) => adjust_class(&mut weights.king, class, delta),
+        Weight::KingMoves => weights.king_moves += delta,
+        Weight::KingRay => weights.king_ray += delta,
+        Weight::Stopper => weights.stopper += delta,
+        Weight::Mobility => weights.mobility += delta,
+    }
+    (weights.max_evaluation() <= MAX_EVALUATION as i64).then_some(weights)
+}
+
+fn adjust_class(table: &mut [i32; 25], class: usize, delta: i32) {
+    for (square, weight) in table.iter_mut().enumerate() {
+        if square_class(square) == class {
+            *weight += delta;
+        }
+    }
+}
+
+/// Fits the weights to `samples`, starting from `start`. `progress` is
+/// called with the error after every pass over the weights.
+pub fn tune(
+    samples: &[Sample],
+    start: &TableEvaluator,
+    mut progress: impl FnMut(f64),
+) -> TableEvaluator {
+    let scale = fit_scale(samples, start);
+    let mut best = start.clone();
+    let mut best_error = error(samples, &best, scale);
+    progress(best_error);
+    let weights = tuned_weights();
+    // Coarse steps first, as the weights range from a few units to hundreds.
+    for step in [16, 4, 1] {
+        for _ in 0..MAX_PASSES {
+            let mut improved = false;
+            for &weight in &weights {
+                for delta in [step, -step] {
+                    let candidate = match adjusted(&best, weight, delta) {
+                        Some(candidate) => candidate,
+                        None => continue,
+                    };
+                    let candidate_error = error(samples, &candidate, scale);
+                    if candidate_error < best_error {
+                        best = candidate;
+                        best_error = candidate_error;
+                        improved = true;
+                        break;
+                    }
+                }
+            }
+            progress(best_error);
+            if !improved {
+                break;
+            }
+        }
+    }
+    best
+}
+
+#[test]
+fn test_square_classes() {
+    for square in 0..25 {
+        let (row, col) = (square / 5, square % 5);
+        let mirrored = row * 5 + 4 - col;
+        let swapped = (4 - row) * 5 + col;
+        assert_eq!(square_class(square), square_class(mirrored));
+        assert_eq!(square_class(square), square_class(swapped));
+    }
+    assert_eq!(square_class(12), CENTRE_CLASS);
+    assert_eq!((0..25).map(square_class).max(), Some(CENTRE_CLASS));
+}
+
+#[test]
+fn test_tune() {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let mut rng = StdRng::seed_from_u64(1);
+    let options = SelfPlayOptions {
+        depth: 2,
+        max_plies: 80,
+        ..SelfPlayOptions::default()
+    };
+    let start = TableEvaluator::default();
+    let games = (0..6)
+        .map(|_| self_play(&options, &start, &mut rng))
+        .collect::<Vec<_>>();
+    let samples = samples(&games);
+    assert!(!samples.is_empty());
+
+    let mut errors = vec![];
+    let tuned = tune(&samples, &start, |error| errors.push(error));
+    assert!(errors.windows(2).all(|w| w[1] <= w[0]));
+    let scale = fit_scale(&samples, &start);
+    assert!(error(&samples, &tuned, scale) <= error(&samples, &start, scale));
+    // The tuned tables keep the symmetry of the default ones.
+    for square in 0..25 {
+        assert_eq!(tuned.pawn[square], tuned.pawn[24 - square]);
+        assert_eq!(tuned.king[square], tuned.king[24 - square]);
+    }
+}
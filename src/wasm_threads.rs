@@ -0,0 +1,141 @@
+//! Parallel search in the browser, for builds with the `wasm-threads`
+//! feature.
+//!
+//! A threaded build shares one linear memory between the search worker and
+//! any number of helper workers, each of which instantiates the same module
+//! on that memory. Before a search, the search worker calls
+//! `Engine::begin_parallel_search`, which puts the search of every helper
+//! in a slot of the engine and returns the numbers of the slots in a
+//! registry shared by all workers. The helpers call `helper_search` with
+//! them and take their search out of the slot, owning their share of the
+//! transposition table from then on. They search until their stop flag is
+//! set, filling the table that the search worker then reads, as native
+//! threads do in `find_best_move`.
+//!
+//! A search left in its slot by a helper that never started is dropped by
+//! the next `begin_parallel_search`, so that it does not keep the table
+//! alive. The registry only holds weak references to the slots, which live
+//! as long as their engine: a helper given a number that is unknown, or
+//! whose engine was freed, does nothing.
+//!
+//! Shared memory needs the standard library to be rebuilt with atomics, so
+//! the threaded build uses a nightly toolchain:
+//!
+//! ```text
+//! RUSTFLAGS='-C target-feature=+atomics,+bulk-memory,+mutable-globals' \
+//!     rustup run nightly wasm-pack build --target web --out-dir pkg-threads \
+//!     -- --features wasm-threads -Z build-std=panic_abort,std
+//! ```
+//!
+//! and the page must be served cross-origin isolated, which `www` already
+//! does for the stop flag. See `www/worker-threads.js` for the JS side.
+
+use std::sync::{Arc, Mutex, Weak};
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    board2::Board2, eval::TableEvaluator, parse_boards, run_helper, tt::TranspositionTable, Engine,
+    SearchOptions,
+};
+
+/// Everything a helper needs to search the same tree as the search worker.
+struct HelperTask {
+    table: Arc<TranspositionTable>,
+    evaluator: TableEvaluator,
+    options: SearchOptions,
+    state: Board2,
+    history_states: Vec<Board2>,
+}
+
+/// Where `begin_parallel_search` leaves the search of one helper until the
+/// helper takes it.
+pub(crate) struct HelperSlot {
+    /// The number of the slot in `SLOTS`.
+    index: usize,
+    task: Mutex<Option<HelperTask>>,
+}
+
+/// The slots of all engines, by number. The entries of freed slots are
+/// reused.
+static SLOTS: Mutex<Vec<Weak<HelperSlot>>> = Mutex::new(Vec::new());
+
+impl HelperSlot {
+    /// Creates an empty slot and registers it under a free number.
+    fn register() -> Arc<HelperSlot> {
+        let mut slots = SLOTS.lock().unwrap();
+        let index = match slots.iter().position(|slot| slot.strong_count() == 0) {
+            Some(index) => index,
+            None => {
+                slots.push(Weak::new());
+                slots.len() - 1
+            }
+        };
+        let slot = Arc::new(HelperSlot {
+            index,
+            task: Mutex::new(None),
+        });
+        slots[index] = Arc::downgrade(&slot);
+        slot
+    }
+}
+
+#[wasm_bindgen]
+impl Engine {
+    /// Prepares the search of `helpers` helper workers on `state`, which
+    /// must be the position and history of the next `find_best_move` or
+    /// `ponder`, and returns the numbers of their slots, one to be passed
+    /// to each call of `helper_search`.
+    pub fn begin_parallel_search(
+        &mut self,
+        helpers: usize,
+        state: Vec<u8>,
+        history_states: Vec<u8>,
+    ) -> Result<Vec<usize>, JsValue> {
+        let (state, mut history_states) = parse_boards(&state, history_states)?;
+        if history_states.last() == Some(&state) {
+            history_states.pop();
+        }
+        while self.helper_slots.len() < helpers {
+            self.helper_slots.push(HelperSlot::register());
+        }
+        let searcher = &self.searcher;
+        let slots = self.helper_slots[..helpers].iter().map(|slot| {
+            // Replacing a search no helper took releases its table.
+            *slot.task.lock().unwrap() = Some(HelperTask {
+                table: searcher.transposition_table.clone(),
+                evaluator: searcher.evaluator.clone(),
+                options: searcher.options(),
+                state,
+                history_states: history_states.clone(),
+            });
+            slot.index
+        });
+        Ok(slots.collect())
+    }
+}
+
+/// Runs helper number `id` of a parallel search, taking its search out of
+/// slot number `slot`, as returned by `Engine::begin_parallel_search`,
+/// until `stop[0]` is set. Returns at once if the slot is unknown or a
+/// later `begin_parallel_search` replaced the search.
+#[wasm_bindgen]
+pub fn helper_search(slot: usize, id: usize, stop: js_sys::Uint8Array) {
+    let slot = match SLOTS.lock().unwrap().get(slot).and_then(Weak::upgrade) {
+        Some(slot) => slot,
+        None => return,
+    };
+    let task = match slot.task.lock().unwrap().take() {
+        Some(task) => task,
+        None => return,
+    };
+    run_helper(
+        &task.table,
+        &task.evaluator,
+        task.state,
+        || js_sys::Atomics::load(&stop, 0).unwrap() != 0,
+        task.options,
+        task.history_states,
+        id,
+    );
+}
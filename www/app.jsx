@@ -1,4 +1,4 @@
-import React, { useReducer, useEffect, useState, useCallback } from "react";
+import React, { useReducer, useEffect, useState, useCallback, useRef } from "react";
 import { Board } from "./board";
 import "./site.scss";
 import { HistoryContext, reduceHistory } from "./history";
@@ -12,41 +12,103 @@ export const App = ({ }) => {
     });
     const [thoughts, setThoughts] = useState([]);
     const [thinking, setThinking] = useState(false);
+    const [error, setError] = useState(null);
     const [stopBuffer, setStopBuffer] = useState(null);
     const [collectFirstMoveScores, setCollectFirstMoveScores] = useState(false);
+    const [multiPv, setMultiPv] = useState(1);
+    // The reply the engine is pondering on, while it waits for the human.
+    const [pondering, setPondering] = useState(null);
+    // Set when the engine's move is played, so that the next search ponders
+    // on the reply expected by its principal variation.
+    const ponderReply = useRef(null);
+    const search = useRef(null);
+    const searchId = useRef(0);
     const board = history.boards[history.currentIndex]?.board;
     useEffect(() => {
         if (!board) {
             return;
         }
-        const stop = new SharedArrayBuffer(1);
+        const running = search.current;
+        if (running && running.ponderBoard && sameBoard(running.ponderBoard, board)) {
+            // Ponder hit: the search goes on, now for the current position.
+            Atomics.store(new Uint8Array(running.stop), 1, 1);
+            running.ponderBoard = null;
+            setPondering(null);
+            setThoughts(running.thoughts);
+            setThinking(!running.done);
+            return;
+        }
+        if (running) {
+            Atomics.store(new Uint8Array(running.stop), 0, 1);
+        }
+        setError(null);
+        setThoughts([]);
+        setPondering(null);
+
+        const historyStates = history.boards.slice(0, history.currentIndex + 1).map(({ board }) => board);
+        const stop = new SharedArrayBuffer(2);
+        const current = { id: ++searchId.current, stop, thoughts: [], ponderBoard: null, done: false };
+        const reply = ponderReply.current;
+        ponderReply.current = null;
+        let searchBoard = board;
+        if (reply) {
+            searchBoard = applyMove(board, reply);
+            historyStates.push(searchBoard);
+            current.ponderBoard = searchBoard;
+            setPondering(reply);
+        }
+        search.current = current;
         setStopBuffer(stop);
         const worker = window.worker;
         worker.postMessage({
-            search: board,
+            id: current.id,
+            search: searchBoard,
             stop,
+            ponder: !!reply,
             collectFirstMoveScores,
-            historyStates: history.boards.slice(0, history.currentIndex + 1).map(({ board }) => board).flat(),
+            multiPv,
+            historyStates: historyStates.flat(),
         });
         worker.onmessage = ({ data: msg }) => {
+            if (msg.id !== current.id) {
+                // Left over from a search that was stopped.
+                return;
+            }
+            // Until the ponder hit, the thoughts are about a position that may
+            // never happen, so they are kept out of sight.
             if (msg.info) {
-                setThoughts(thoughts => [JSON.parse(msg.info), ...thoughts]);
+                current.thoughts = [JSON.parse(msg.info), ...current.thoughts];
+                if (!current.ponderBoard) {
+                    setThoughts(current.thoughts);
+                }
+            }
+            if (msg.move !== undefined) {
+                current.done = true;
+                if (!current.ponderBoard) {
+                    setThinking(false);
+                }
+            }
+            if (msg.error) {
+                current.done = true;
+                setError(msg.error);
+                setThinking(false);
             }
         };
         setThinking(true);
-        return () => {
-            Atomics.store(new Uint8Array(stop), 0, 1);
-            setThinking(false);
-            setThoughts([]);
-            setStopBuffer(null);
-            worker.onmessage = undefined;
-        };
-    }, [board, collectFirstMoveScores]);
+    }, [board, collectFirstMoveScores, multiPv]);
+
+    useEffect(() => () => {
+        if (search.current) {
+            Atomics.store(new Uint8Array(search.current.stop), 0, 1);
+        }
+    }, []);
 
     let thinkingMove = null;
+    let thoughtReply = null;
     if (thoughts.length > 0) {
         const thought = thoughts[0];
         thinkingMove = thought.result.best_path[thought.result.best_path.length - 1];
+        thoughtReply = thought.result.best_path[thought.result.best_path.length - 2] || null;
     }
 
     const stopThinking = useCallback(() => {
@@ -57,14 +119,23 @@ export const App = ({ }) => {
         <HistoryContext.Provider value={{ history, dispatch }}>
             <div className="app-container">
                 <div className="controls">
-                    <button onClick={() => dispatch({ newGame: [0, 1, 3, 4, 20, 21, 23, 24, 22, 2, 1] })}>New Game</button>
+                    <button onClick={() => {
+                        window.worker.postMessage({ newGame: true });
+                        dispatch({ newGame: [0, 1, 3, 4, 20, 21, 23, 24, 22, 2, 1] });
+                    }}>New Game</button>
                     <button onClick={stopThinking} disabled={!thinking}>Stop Thinking</button>
-                    <button onClick={() => dispatch({ move: thinkingMove })} disabled={thinkingMove === null}>
+                    <button onClick={() => {
+                        ponderReply.current = thoughtReply;
+                        dispatch({ move: thinkingMove });
+                    }} disabled={thinkingMove === null}>
                         Make move {thinkingMove == null ? "?" : `${thinkingMove.from} -> ${thinkingMove.to}`}
                     </button>
                     <button onClick={() => setCollectFirstMoveScores(x => !x)}>
                         Collect First Move Scores (slower): {collectFirstMoveScores ? "YES" : "no"}
                     </button>
+                    <button onClick={() => setMultiPv(x => x >= 5 ? 1 : x + 2)}>
+                        Lines: {multiPv}
+                    </button>
                 </div>
                 <div className="top-panel">
                     {history.boards.length > 0 && <div className="top-panel-board">
@@ -73,19 +144,32 @@ export const App = ({ }) => {
                     <HistoryView></HistoryView>
                 </div>
                 <div className="bottom-panel">
+                    {pondering && <div className="pondering">Pondering on the expected reply {pondering.from} -&gt; {pondering.to}</div>}
+                    {error && <div className="error">Cannot search this position: {error}</div>}
                     <div className="thoughts">
                         {thoughts.map(({
                             depth,
+                            mate,
                             nodes_searched: nodesSearched,
+                            quiescence_nodes: quiescenceNodes,
+                            cutoffs,
+                            first_move_cutoffs: firstMoveCutoffs,
                             transposition_table_size: transpositionTableSize,
                             result: {
                                 score,
                                 best_path: bestPath
-                            } }) => {
+                            },
+                            lines }) => {
                             return <div key={depth} className="thought">
                                 <div className="depth-header">
-                                    Depth {depth}, Score: {score} Nodes: {humanDisplay(nodesSearched)} Transposition Table: {humanDisplay(transpositionTableSize)}
+                                    Depth {depth}, Score: {mate == null ? score : `${mate > 0 ? "White" : "Black"} wins in ${Math.abs(mate)}`} Nodes: {humanDisplay(nodesSearched)} (+{humanDisplay(quiescenceNodes)} quiescence) First Move Cutoffs: {Math.round(100 * firstMoveCutoffs / Math.max(cutoffs, 1))}% Transposition Table: {humanDisplay(transpositionTableSize)}
                                 </div>
+                                {lines.length > 1 && <div className="thought-lines">
+                                    {lines.map((line, i) => <div key={i}>
+                                        {i + 1}. {line.mate == null ? line.score : `${line.mate > 0 ? "White" : "Black"} wins in ${Math.abs(line.mate)}`}:{" "}
+                                        {[...line.best_path].reverse().map(({ from, to }) => `${from} -> ${to}`).join(", ")}
+                                    </div>)}
+                                </div>}
                                 <div className="thought-boards">
                                     {(() => {
                                         const boards = [{ board, move: undefined }];
@@ -121,4 +205,8 @@ function humanDisplay(n) {
         return (Math.floor(n / 100) / 10) + 'K';
     }
     return (Math.floor(n / 100000) / 10) + 'M';
-}
\ No newline at end of file
+}
+
+function sameBoard(a, b) {
+    return a.length === b.length && a.every((x, i) => x === b[i]);
+}
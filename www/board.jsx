@@ -19,6 +19,40 @@ const ALL_DIRS = [
     [1, 1],
 ];
 
+// The squares a piece on `from` passes through when sliding in direction
+// `[dx, dy]`, ending with the one it stops on.
+function slide(flat, from, [dx, dy]) {
+    const squares = [];
+    let target = from;
+    while (true) {
+        let [i, j] = [Math.floor(target / 5), target % 5];
+        let [ii, jj] = [i + dx, j + dy];
+        if (ii >= 0 && ii < 5 && jj >= 0 && jj < 5 && flat[ii * 5 + jj] === '') {
+            target = ii * 5 + jj;
+            squares.push(target);
+        } else {
+            break;
+        }
+    }
+    return squares;
+}
+
+// The ray along which the king of the side that just moved can slide onto the
+// centre next move, unless the side to move stops it, as `Board2::threats`.
+function threatRay(flat, isWhitesTurn) {
+    const king = flat.indexOf(isWhitesTurn ? 'bk' : 'wk');
+    if (king == 12 || flat[12] != '') {
+        return [];
+    }
+    for (const dir of ALL_DIRS) {
+        const squares = slide(flat, king, dir);
+        if (squares[squares.length - 1] == 12) {
+            return squares;
+        }
+    }
+    return [];
+}
+
 export const Board = ({ board, lastMove, onMove, canMove, turnNumber }) => {
     const flat = [];
     for (let i = 0; i < 25; i++) {
@@ -39,25 +73,19 @@ export const Board = ({ board, lastMove, onMove, canMove, turnNumber }) => {
     const [selectedPiece, setSelectedPiece] = useState(null);
     const eligibleTargets = new Set();
     if (selectedPiece !== null) {
-        for (const [dx, dy] of ALL_DIRS) {
-            let target = selectedPiece;
-            while (true) {
-                let [i, j] = [Math.floor(target / 5), target % 5];
-                let [ii, jj] = [i + dx, j + dy];
-                if (ii >= 0 && ii < 5 && jj >= 0 && jj < 5 && flat[ii * 5 + jj] === '') {
-                    target = ii * 5 + jj;
-                } else {
-                    break;
-                }
+        for (const dir of ALL_DIRS) {
+            const squares = slide(flat, selectedPiece, dir);
+            if (squares.length == 0) {
+                continue;
             }
+            const target = squares[squares.length - 1];
             if (target == 12 && (flat[selectedPiece] != 'bk' && flat[selectedPiece] != 'wk')) {
                 continue;
             }
-            if (target !== selectedPiece) {
-                eligibleTargets.add(target);
-            }
+            eligibleTargets.add(target);
         }
     }
+    const threatened = new Set(threatRay(flat, isWhitesTurn));
 
     const clickHandler = useCallback((e) => {
         const index = parseInt(e.currentTarget.getAttribute('pieceindex'));
@@ -90,7 +118,8 @@ export const Board = ({ board, lastMove, onMove, canMove, turnNumber }) => {
                         ${lastMove?.from === index ? 'from-cell' : ''}
                         ${lastMove?.to === index ? 'to-cell' : ''}
                         ${eligibleTargets.has(index) ? 'candidate-cell' : ''}
-                        ${selectedPiece === index ? 'selected-cell' : ''}`}
+                        ${selectedPiece === index ? 'selected-cell' : ''}
+                        ${threatened.has(index) ? 'threat-cell' : ''}`}
                         pieceindex={index}
                         onClick={clickHandler}>
                         {flat[index] != '' &&
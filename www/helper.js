@@ -0,0 +1,17 @@
+import init, { helper_search } from "penguin-threads";
+
+// Helper of worker-threads.js. The module is instantiated on the memory of
+// the search worker, so that both see the same transposition table.
+let ready = null;
+
+self.onmessage = async ({ data: msg }) => {
+    if (!ready) {
+        ready = init(msg.module, msg.memory);
+    }
+    await ready;
+    try {
+        helper_search(msg.slot, msg.id, new Uint8Array(msg.stop));
+    } catch (error) {
+        console.error(error);
+    }
+}
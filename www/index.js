@@ -2,7 +2,10 @@ import { App } from "./app";
 import React from "react";
 import ReactDOM from "react-dom/client";
 
-window.worker = new Worker(new URL("./worker.js", import.meta.url));
+// PENGUIN_THREADS is set by `npm run build:threads`, see webpack.config.js.
+window.worker = PENGUIN_THREADS
+    ? new Worker(new URL("./worker-threads.js", import.meta.url), { type: "module" })
+    : new Worker(new URL("./worker.js", import.meta.url));
 
 const root = ReactDOM.createRoot(document.getElementById('app'));
 root.render(React.createElement(App));
@@ -1,18 +1,28 @@
 const CopyWebpackPlugin = require("copy-webpack-plugin");
+const fs = require('fs');
 const path = require('path');
+const webpack = require('webpack');
 
-module.exports = {
+// `--env threads` bundles the `wasm-threads` build from ../pkg-threads
+// instead, see src/wasm_threads.rs. `--env weights=<file>` bundles
+// evaluation weights in JSON for the engine, see src/eval.rs.
+module.exports = (env = {}) => ({
   entry: "./index.js",
   output: {
     path: path.resolve(__dirname, "dist"),
     filename: "index.js",
   },
   resolve: {
-    extensions: ['', '.js', '.jsx', '.scss']
+    extensions: ['', '.js', '.jsx', '.scss'],
+    alias: env.threads ? { 'penguin-threads': path.resolve(__dirname, '../pkg-threads') } : {},
   },
   mode: "development",
   plugins: [
-    new CopyWebpackPlugin(['index.html'])
+    new CopyWebpackPlugin(['index.html']),
+    new webpack.DefinePlugin({
+      PENGUIN_THREADS: JSON.stringify(!!env.threads),
+      PENGUIN_WEIGHTS: env.weights ? JSON.stringify(fs.readFileSync(env.weights, "utf8")) : "undefined",
+    }),
   ],
   experiments: {
     asyncWebAssembly: true
@@ -43,4 +53,4 @@ module.exports = {
       },
     ]
   }
-};
+});
@@ -0,0 +1,63 @@
+import init, { Engine } from "penguin-threads";
+
+// Like worker.js, but for the `wasm-threads` build: every search is helped
+// by one helper worker per additional core, sharing this worker's memory.
+const ready = init();
+const helpers = [];
+// One engine for the whole game, as in worker.js.
+let searchId;
+let engine;
+
+self.onmessage = async ({ data: msg }) => {
+    const wasm = await ready;
+    if (!engine) {
+        engine = new Engine((data) => {
+            self.postMessage({ id: searchId, info: data });
+        }, PENGUIN_WEIGHTS);
+    }
+    if (msg.newGame) {
+        engine.new_game();
+        return;
+    }
+    searchId = msg.id;
+
+    const helperCount = Math.max(0, (navigator.hardwareConcurrency || 1) - 1);
+    while (helpers.length < helperCount) {
+        helpers.push(new Worker(new URL("./helper.js", import.meta.url), { type: "module" }));
+    }
+    const helperStop = new SharedArrayBuffer(1);
+
+    let move;
+    try {
+        if (msg.repetitionRule) {
+            engine.set_repetition_rule(msg.repetitionRule);
+        }
+        if (msg.multiPv) {
+            engine.set_multi_pv(msg.multiPv);
+        }
+        const slots = engine.begin_parallel_search(helpers.length, new Uint8Array(msg.search),
+            msg.historyStates);
+        helpers.forEach((helper, i) => helper.postMessage({
+            module: init.__wbindgen_wasm_module,
+            memory: wasm.memory,
+            slot: slots[i],
+            id: i + 1,
+            stop: helperStop,
+        }));
+        // A pondering search waits for stop[1] (the ponder hit) before
+        // applying its limits.
+        const search = msg.ponder ? engine.ponder.bind(engine) : engine.find_best_move.bind(engine);
+        move = search(new Uint8Array(msg.stop), new Uint8Array(msg.search), msg.collectFirstMoveScores,
+            msg.historyStates, JSON.stringify(msg.limits || {}));
+    } catch (error) {
+        console.error(error);
+        self.postMessage({ id: msg.id, error: String(error) });
+        return;
+    } finally {
+        Atomics.store(new Uint8Array(helperStop), 0, 1);
+    }
+    self.postMessage({
+        id: msg.id,
+        move,
+    });
+}
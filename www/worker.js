@@ -1,17 +1,43 @@
 import { Engine } from "penguin";
 
+// One engine for the whole game, so that each search reuses what the
+// previous ones learned. Partial results are tagged with the current search.
+let searchId;
+// PENGUIN_WEIGHTS is set by `--env weights=<file>`, see webpack.config.js.
+const engine = new Engine((data) => {
+    console.log(data);
+    self.postMessage({ id: searchId, info: data });
+}, PENGUIN_WEIGHTS);
+
 self.onmessage = ({ data: msg }) => {
     console.log(msg);
-    const engine = new Engine(
-        new Uint8Array(msg.stop),
-        (data) => {
-            console.log(data);
-            self.postMessage({ info: data });
-        });
+    if (msg.newGame) {
+        engine.new_game();
+        return;
+    }
+    searchId = msg.id;
 
-    const move = engine.find_best_move(new Uint8Array(msg.search), msg.collectFirstMoveScores, msg.historyStates);
+    let move;
+    try {
+        if (msg.repetitionRule) {
+            engine.set_repetition_rule(msg.repetitionRule);
+        }
+        if (msg.multiPv) {
+            engine.set_multi_pv(msg.multiPv);
+        }
+        // A pondering search waits for stop[1] (the ponder hit) before
+        // applying its limits.
+        const search = msg.ponder ? engine.ponder.bind(engine) : engine.find_best_move.bind(engine);
+        move = search(new Uint8Array(msg.stop), new Uint8Array(msg.search), msg.collectFirstMoveScores,
+            msg.historyStates, JSON.stringify(msg.limits || {}));
+    } catch (error) {
+        console.error(error);
+        self.postMessage({ id: msg.id, error: String(error) });
+        return;
+    }
     console.log(move);
     self.postMessage({
+        id: msg.id,
         move,
     });
 }